@@ -0,0 +1,181 @@
+use super::{
+    plan::points_into,
+    state::state_dir,
+    utils::{print_error, print_info},
+};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{create_dir, create_dir_all, read_dir, remove_dir_all, remove_file, rename, File},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const INDEX: &str = "index.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupEntry {
+    original: PathBuf,
+    backup: PathBuf,
+    /// Repo whose link the file was moved out of the way of.
+    repo: PathBuf,
+}
+
+/// A timestamped directory under the state dir holding files that were
+/// moved out of the way of a link. The directory is only created once the
+/// first file is stored, so runs without conflicts leave nothing behind.
+pub struct Backup {
+    repo: PathBuf,
+    dir: Option<PathBuf>,
+    entries: Vec<BackupEntry>,
+}
+
+fn backup_root() -> Result<PathBuf> {
+    Ok(state_dir()?.join("backups"))
+}
+
+fn read_index(dir: &Path) -> Result<Vec<BackupEntry>> {
+    let reader = File::open(dir.join(INDEX))
+        .context(format!("unable to read backup index in {}", dir.display()))?;
+    Ok(serde_json::from_reader(reader)?)
+}
+
+fn write_index(dir: &Path, entries: &[BackupEntry]) -> Result<()> {
+    let writer = File::create(dir.join(INDEX))
+        .context(format!("unable to write backup index in {}", dir.display()))?;
+    serde_json::to_writer_pretty(writer, entries)?;
+    Ok(())
+}
+
+/// Creates a new set directory named after the current time. Runs started
+/// within the same nanosecond get a numbered suffix, so two runs never share
+/// an index.
+fn create_set() -> Result<PathBuf> {
+    let root = backup_root()?;
+    create_dir_all(&root).context(format!("unable to create {}", root.display()))?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let name = format!("{}.{:09}", now.as_secs(), now.subsec_nanos());
+    let mut dir = root.join(&name);
+    let mut attempt = 0;
+    loop {
+        match create_dir(&dir) {
+            Ok(_) => return Ok(dir),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                attempt += 1;
+                dir = root.join(format!("{name}-{attempt}"));
+            }
+            Err(e) => return Err(e).context(format!("unable to create {}", dir.display())),
+        }
+    }
+}
+
+impl Backup {
+    pub fn new(repo: &Path) -> Self {
+        Self {
+            repo: repo.to_path_buf(),
+            dir: None,
+            entries: vec![],
+        }
+    }
+
+    /// Moves `file` into the backup dir, keeping its absolute path as the
+    /// layout inside the backup so sets stay easy to browse by hand.
+    pub fn store(&mut self, file: &Path) -> Result<PathBuf> {
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let dir = create_set()?;
+                self.dir = Some(dir.clone());
+                dir
+            }
+        };
+        let relative = file.strip_prefix("/").unwrap_or(file);
+        let backup = dir.join(relative);
+
+        if backup.exists() {
+            return Err(anyhow!("backup {} already exists", backup.display()));
+        }
+        if let Some(parent) = backup.parent() {
+            create_dir_all(parent).context(format!("unable to create {}", parent.display()))?;
+        }
+        rename(file, &backup).context(format!(
+            "unable to move {} to {}",
+            file.display(),
+            backup.display()
+        ))?;
+
+        self.entries.push(BackupEntry {
+            original: file.to_path_buf(),
+            backup: backup.clone(),
+            repo: self.repo.clone(),
+        });
+        write_index(&dir, &self.entries)?;
+        Ok(backup)
+    }
+}
+
+/// Moves every file backed up while syncing `repo` back into place, newest
+/// backup first, returning the restored paths. Links into `repo` standing in
+/// the way are removed, anything else is left alone.
+pub fn restore_backups(repo: &Path) -> Result<Vec<PathBuf>> {
+    let repo = repo.canonicalize()?;
+    let mut restored = vec![];
+    let root = backup_root()?;
+    if !root.is_dir() {
+        print_info("No backups to restore".to_string());
+        return Ok(restored);
+    }
+
+    let mut sets: Vec<PathBuf> = read_dir(&root)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.join(INDEX).is_file())
+        .collect();
+    sets.sort();
+    sets.reverse();
+
+    for set in sets {
+        let mut remaining: Vec<BackupEntry> = vec![];
+
+        for entry in read_index(&set)? {
+            if entry.repo != repo {
+                remaining.push(entry);
+                continue;
+            }
+            if entry.original.is_symlink() && points_into(&entry.original, &repo) {
+                remove_file(&entry.original)?;
+            }
+            if entry.original.exists() || entry.original.is_symlink() {
+                print_error(format!(
+                    "{} is in the way, leaving backup at {}",
+                    entry.original.display(),
+                    entry.backup.display()
+                ));
+                remaining.push(entry);
+                continue;
+            }
+            if let Some(parent) = entry.original.parent() {
+                create_dir_all(parent)?;
+            }
+            rename(&entry.backup, &entry.original)
+                .context(format!("unable to restore {}", entry.original.display()))?;
+            print_info(format!(
+                "restored {} from {}",
+                entry.original.display(),
+                entry.backup.display()
+            ));
+            restored.push(entry.original);
+        }
+
+        if remaining.is_empty() {
+            remove_dir_all(&set)?;
+        } else {
+            write_index(&set, &remaining)?;
+        }
+    }
+    Ok(restored)
+}
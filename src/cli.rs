@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -6,10 +6,28 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 #[derive(Debug, Parser)]
 #[clap(author="Luna D. Dragon", version=VERSION, about="My cli tool to manage dotfiles", long_about = None)]
 pub struct Cli {
+    #[clap(
+        id = "format",
+        value_name = "FORMAT",
+        long = "output",
+        global = true,
+        value_enum,
+        default_value_t = Output::Table,
+        help = "how sync, plan, verify, status and vars print their results, logs go to stderr"
+    )]
+    pub output: Output,
     #[clap(subcommand)]
     pub command: Commands,
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    Json,
+    #[default]
+    Table,
+    Plain,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     #[clap(about = "Init a directory for dotfox")]
@@ -21,6 +39,12 @@ pub enum Commands {
     Clone {
         #[clap(help = "url of repoistory")]
         url: String,
+        #[clap(
+            long = "profile",
+            value_delimiter = ',',
+            help = "profiles to deploy, instead of the ones saved with dotfox profile"
+        )]
+        profile: Vec<String>,
         #[clap(help = "path to repo, defaults to repositry name")]
         path: Option<PathBuf>,
     },
@@ -33,6 +57,19 @@ pub enum Commands {
     },
     #[clap(about = "sync repo to home directory")]
     Sync {
+        #[clap(flatten)]
+        args: SyncArgs,
+        #[clap(help = "path to repo, optional defaults to current dir")]
+        path: Option<PathBuf>,
+    },
+    #[clap(about = "show every action sync would take without applying it")]
+    Plan {
+        #[clap(
+            long = "profile",
+            value_delimiter = ',',
+            help = "profiles to plan for, instead of the ones saved with dotfox profile"
+        )]
+        profile: Vec<String>,
         #[clap(help = "path to repo, optional defaults to current dir")]
         path: Option<PathBuf>,
     },
@@ -50,12 +87,187 @@ pub enum Commands {
     },
     #[clap(about = "pull & sync changes from the git repo")]
     Pull {
+        #[clap(
+            long = "profile",
+            value_delimiter = ',',
+            help = "profiles to deploy, instead of the ones saved with dotfox profile"
+        )]
+        profile: Vec<String>,
+        #[clap(help = "path to repo, optional defaults to current dir")]
+        path: Option<PathBuf>,
+    },
+    #[clap(about = "remove links created by sync from the home directory")]
+    Unsync {
+        #[clap(
+            long = "restore-backups",
+            help = "move backed up files back into place afterwards"
+        )]
+        restore_backups: bool,
+        #[clap(
+            long = "profile",
+            value_delimiter = ',',
+            help = "profiles to remove links for, instead of the ones saved with dotfox profile"
+        )]
+        profile: Vec<String>,
+        #[clap(help = "path to repo, optional defaults to current dir")]
+        path: Option<PathBuf>,
+    },
+    #[clap(
+        name = "restore-backups",
+        about = "move files backed up while syncing this repo back into place"
+    )]
+    RestoreBackups {
+        #[clap(help = "path to repo, optional defaults to current dir")]
+        path: Option<PathBuf>,
+    },
+    #[clap(about = "remove stale links pointing into the repo")]
+    Prune {
+        #[clap(short = 'y', long = "yes", help = "don't ask before removing links")]
+        yes: bool,
+        #[clap(
+            long = "profile",
+            value_delimiter = ',',
+            help = "profiles to resolve links for, instead of the ones saved with dotfox profile"
+        )]
+        profile: Vec<String>,
+        #[clap(help = "path to repo, optional defaults to current dir")]
+        path: Option<PathBuf>,
+    },
+    #[clap(about = "show the variables resolved for this machine")]
+    Vars {
+        #[clap(
+            long = "profile",
+            value_delimiter = ',',
+            help = "profiles to resolve variables for, instead of the ones saved with dotfox profile"
+        )]
+        profile: Vec<String>,
         #[clap(help = "path to repo, optional defaults to current dir")]
         path: Option<PathBuf>,
     },
-    #[clap(about = "verify integrity of all symlinks")]
+    #[clap(
+        about = "verify integrity of all symlinks",
+        long_about = "verify integrity of all symlinks\n\nExits with 0 when every target is ok, 1 on errors, otherwise with a bit set for every kind of problem found: 2 missing, 4 dangling, 8 real file, 16 foreign link, 32 outdated, 64 undecryptable"
+    )]
     Verify {
+        #[clap(long = "fix", help = "repair every target that isn't ok")]
+        fix: bool,
+        #[clap(
+            long = "on-conflict",
+            value_enum,
+            requires = "fix",
+            default_value_t = ConflictPolicy::Skip,
+            help = "with --fix, what to do when a real file is in the way of a link"
+        )]
+        on_conflict: ConflictPolicy,
+        #[clap(
+            long = "profile",
+            value_delimiter = ',',
+            help = "profiles to verify, instead of the ones saved with dotfox profile"
+        )]
+        profile: Vec<String>,
+        #[clap(help = "path to repo, optional defaults to current dir")]
+        path: Option<PathBuf>,
+    },
+    #[clap(about = "show uncommitted changes, upstream state and links that are out of place")]
+    Status {
+        #[clap(
+            long = "profile",
+            value_delimiter = ',',
+            help = "profiles to check links for, instead of the ones saved with dotfox profile"
+        )]
+        profile: Vec<String>,
+        #[clap(help = "path to repo, optional defaults to current dir")]
+        path: Option<PathBuf>,
+    },
+    #[clap(about = "validate the config or print its json schema")]
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommands,
+    },
+    #[clap(about = "save the profiles used on this machine, or show them")]
+    Profile {
+        #[clap(
+            long = "set",
+            value_delimiter = ',',
+            help = "profiles to save, comma separated"
+        )]
+        set: Vec<String>,
+        #[clap(long = "clear", help = "forget the saved profiles")]
+        clear: bool,
+    },
+    #[clap(about = "encrypt a file to the recipients in the config and stage it")]
+    Encrypt {
+        #[clap(help = "file to encrypt")]
+        file: PathBuf,
+        #[clap(
+            short = 'o',
+            long = "out",
+            help = "where to write the encrypted file, defaults to <file>.gpg or <file>.age"
+        )]
+        output: Option<PathBuf>,
+        #[clap(
+            long = "armor",
+            help = "write ascii armored output, <file>.asc with gpg"
+        )]
+        armor: bool,
+        #[clap(help = "path to repo, optional defaults to current dir")]
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommands {
+    #[clap(about = "check the config for mistakes and that every folder exists")]
+    Validate {
         #[clap(help = "path to repo, optional defaults to current dir")]
         path: Option<PathBuf>,
     },
+    #[clap(about = "print the json schema of the config")]
+    Schema {
+        #[clap(
+            short = 'o',
+            long = "out",
+            help = "write the schema to this file instead"
+        )]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Default, Args)]
+pub struct SyncArgs {
+    #[clap(
+        long = "dry-run",
+        help = "show what would be done without touching the home directory"
+    )]
+    pub dry_run: bool,
+    #[clap(
+        long = "on-conflict",
+        value_enum,
+        default_value_t = ConflictPolicy::Skip,
+        help = "what to do when a real file is in the way of a link"
+    )]
+    pub on_conflict: ConflictPolicy,
+    #[clap(long = "prune", help = "remove stale links pointing into the repo")]
+    pub prune: bool,
+    #[clap(short = 'y', long = "yes", help = "don't ask before removing links")]
+    pub yes: bool,
+    #[clap(
+        long = "profile",
+        value_delimiter = ',',
+        help = "profiles to deploy, instead of the ones saved with dotfox profile"
+    )]
+    pub profile: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConflictPolicy {
+    #[clap(help = "move the file to a timestamped backup and link")]
+    Backup,
+    #[default]
+    #[clap(help = "leave the file alone and report it")]
+    Skip,
+    #[clap(help = "delete the file and link")]
+    Overwrite,
+    #[clap(help = "stop before changing anything")]
+    Abort,
 }
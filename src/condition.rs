@@ -0,0 +1,113 @@
+use super::config::{Host, Hostname, StringOrMap};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer};
+use std::{
+    env::{split_paths, var, var_os},
+    fmt::{self, Display},
+    os::unix::fs::PermissionsExt,
+};
+
+/// A `when` condition tree, each node is an object with a single key like
+/// `{"all": [...]}`, `{"not": {...}}` or `{"executable": "sway"}`.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Condition {
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+    Hostname(Hostname),
+    /// `OS-ARCH` like `linux-x86_64`.
+    Os(String),
+    /// `ID` from `/etc/os-release`.
+    Distro(String),
+    Username(String),
+    Env(EnvCondition),
+    /// Name of an executable that must be on `PATH`.
+    Executable(String),
+    /// A profile that must be active.
+    Profile(String),
+}
+
+/// An environment variable that has to be set, or set to a specific value.
+#[derive(Debug, JsonSchema)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum EnvCondition {
+    Set(String),
+    Value { name: String, value: String },
+}
+
+impl<'de> Deserialize<'de> for EnvCondition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Value {
+            name: String,
+            value: String,
+        }
+
+        Ok(match StringOrMap::<Value>::deserialize(deserializer)? {
+            StringOrMap::String(name) => EnvCondition::Set(name),
+            StringOrMap::Map(Value { name, value }) => EnvCondition::Value { name, value },
+        })
+    }
+}
+
+/// Whether an executable file called `name` is in one of the `PATH` dirs.
+fn on_path(name: &str) -> bool {
+    let Some(path) = var_os("PATH") else {
+        return false;
+    };
+    split_paths(&path).any(|dir| {
+        dir.join(name)
+            .metadata()
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    })
+}
+
+impl Condition {
+    pub fn matches(&self, host: &Host) -> bool {
+        match self {
+            Condition::All(conditions) => conditions.iter().all(|c| c.matches(host)),
+            Condition::Any(conditions) => conditions.iter().any(|c| c.matches(host)),
+            Condition::Not(condition) => !condition.matches(host),
+            Condition::Hostname(hostname) => hostname.matches(&host.hostname),
+            Condition::Os(os) => *os == host.os,
+            Condition::Distro(distro) => host.distro.as_ref() == Some(distro),
+            Condition::Username(username) => *username == host.username,
+            Condition::Env(EnvCondition::Set(name)) => var_os(name).is_some(),
+            Condition::Env(EnvCondition::Value { name, value }) => {
+                var(name).is_ok_and(|set| set == *value)
+            }
+            Condition::Executable(name) => on_path(name),
+            Condition::Profile(profile) => host.profiles.contains(profile),
+        }
+    }
+}
+
+fn join(conditions: &[Condition]) -> String {
+    conditions
+        .iter()
+        .map(|condition| condition.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::All(conditions) => write!(f, "all({})", join(conditions)),
+            Condition::Any(conditions) => write!(f, "any({})", join(conditions)),
+            Condition::Not(condition) => write!(f, "not({condition})"),
+            Condition::Hostname(hostname) => write!(f, "hostname={}", hostname.describe()),
+            Condition::Os(os) => write!(f, "os={os}"),
+            Condition::Distro(distro) => write!(f, "distro={distro}"),
+            Condition::Username(username) => write!(f, "username={username}"),
+            Condition::Env(EnvCondition::Set(name)) => write!(f, "env={name}"),
+            Condition::Env(EnvCondition::Value { name, value }) => {
+                write!(f, "env={name}:{value}")
+            }
+            Condition::Executable(name) => write!(f, "executable={name}"),
+            Condition::Profile(profile) => write!(f, "profile={profile}"),
+        }
+    }
+}
@@ -1,90 +1,738 @@
+use super::{condition::Condition, state::load_profiles};
 use anyhow::{anyhow, Result};
+use globset::{Glob, GlobMatcher};
 use platform_info::{PlatformInfo, PlatformInfoAPI, UNameAPI};
-use serde::Deserialize;
+use regex::Regex;
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{Metadata, Schema, SchemaObject, SubschemaValidation},
+    JsonSchema,
+};
+use serde::{
+    de::{self, value::MapAccessDeserializer, MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
 use std::{
+    collections::BTreeMap,
     env::consts::{ARCH, OS},
-    path::PathBuf,
+    fmt::{self, Display},
+    fs::read_to_string,
+    marker::PhantomData,
+    path::{Path, PathBuf},
 };
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    /// Editors use this to find the schema, ignored by dotfox.
+    #[serde(rename = "$schema")]
+    _schema: Option<String>,
+    /// Other config files layered on top of this one, relative to the repo.
+    #[serde(default)]
+    pub include: Vec<Include>,
+    #[serde(default)]
     pub config: Vec<Programs>,
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    #[serde(default)]
+    pub overrides: Vec<Override>,
+    #[serde(default)]
+    pub secrets: Secrets,
+    #[serde(default)]
+    pub hooks: Hooks,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Secrets {
+    #[serde(default)]
+    pub backend: Backend,
+    /// Keys `dotfox encrypt` encrypts to, anything gpg can look a key up by,
+    /// or age and ssh public keys with the age backend.
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// age identity files or ssh private keys used to decrypt `.age` files.
+    #[serde(default)]
+    pub identities: Vec<String>,
+    /// Encrypt with a passphrase instead of recipients, age only.
+    #[serde(default)]
+    pub passphrase: bool,
+}
+
+/// Which tool `dotfox encrypt` uses.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Gpg,
+    Age,
 }
 
-#[derive(Debug, Deserialize)]
+/// Either a bare path, or a path only included on matching machines.
+#[derive(Debug, JsonSchema)]
+#[serde(untagged)]
+pub enum Include {
+    Path(PathBuf),
+    Conditional(Box<ConditionalInclude>),
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConditionalInclude {
+    path: PathBuf,
+    os: Option<String>,
+    hostname: Option<Hostname>,
+    when: Option<Condition>,
+}
+
+/// Variables that replace the top-level ones on matching machines.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Override {
+    os: Option<String>,
+    hostname: Option<Hostname>,
+    when: Option<Condition>,
+    pub variables: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Programs {
     os: Option<String>,
     hostname: Option<Hostname>,
-    folder: PathBuf,
+    when: Option<Condition>,
+    /// Only deployed while one of these profiles is active.
+    #[serde(default)]
+    profiles: Vec<String>,
+    pub folder: PathBuf,
+    pub target: Option<String>,
+    #[serde(default)]
+    pub mode: Mode,
+    #[serde(default)]
+    pub granularity: Granularity,
+    #[serde(default)]
+    pub template: bool,
+    #[serde(default)]
+    pub hooks: Hooks,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum Hostname {
-    Single(String),
-    Multiple(Vec<String>),
+/// Shell commands run around syncing, keyed by the event that triggers them.
+///
+/// Hooks run with `sh -c`, global ones from the repo and folder ones from
+/// their folder, global hooks first. A failing hook stops the command.
+///
+/// Every hook gets `DOTFOX_EVENT` set to `pre_sync`, `post_sync`, `post_pull`
+/// or `post_clone`, `DOTFOX_REPO` to the absolute path of the repo,
+/// `DOTFOX_HOSTNAME` and `DOTFOX_OS` to the machine as matched by the config
+/// and `DOTFOX_FOLDERS` to every folder matched on this machine, separated by
+/// `:`. Folder hooks also get `DOTFOX_FOLDER`, the folder they belong to.
+#[derive(Debug, Default, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    /// Before anything is linked.
+    #[serde(default)]
+    pub pre_sync: Vec<Hook>,
+    /// After every link is in place.
+    #[serde(default)]
+    pub post_sync: Vec<Hook>,
+    /// After `dotfox pull` synced the pulled changes.
+    #[serde(default)]
+    pub post_pull: Vec<Hook>,
+    /// After `dotfox clone` synced the new repo.
+    #[serde(default)]
+    pub post_clone: Vec<Hook>,
 }
 
-impl Config {
-    pub fn folders(self) -> Result<Vec<PathBuf>> {
-        let sys = match PlatformInfo::new() {
-            Ok(sys) => sys,
-            Err(e) => return Err(anyhow!(e.to_string())),
+/// Either a bare command that runs every time, or `{"run": ..., "when": ...}`
+/// to run it `once` or `on_change`.
+#[derive(Debug, Clone, JsonSchema)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Hook {
+    Command(String),
+    Detailed {
+        run: String,
+        #[serde(default)]
+        when: When,
+    },
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum When {
+    /// Every time the event fires.
+    #[default]
+    Always,
+    /// Only until the hook first succeeds on this machine. A hook is told
+    /// apart by its repo, folder, event and command, so editing the command
+    /// runs it again.
+    Once,
+    /// Whenever the content of its folder, or of every matched folder for
+    /// global hooks, changed since the hook last succeeded.
+    OnChange,
+}
+
+/// How files from a folder end up in the home directory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Symlink,
+    Copy,
+    Hardlink,
+    /// Rendered from a template, never set in the config itself.
+    #[serde(skip)]
+    Template,
+    /// Decrypted from an encrypted file, never set in the config itself.
+    #[serde(skip)]
+    Secret,
+}
+
+#[derive(Debug)]
+pub enum Hostname {
+    Single(HostPattern),
+    Multiple(Vec<HostPattern>),
+}
+
+/// A hostname as written in the config: an exact name, a glob like
+/// `build-*`, or a regex between slashes like `/^build-\d+$/`. A leading `!`
+/// negates the pattern.
+#[derive(Debug, Clone)]
+pub struct HostPattern {
+    raw: String,
+    negated: bool,
+    matcher: Matcher,
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Exact(String),
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+/// Whether each top-level entry of a folder is linked as a whole, or its
+/// directories are recreated and only the files inside are linked.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    #[default]
+    Entry,
+    File,
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self {
+            Mode::Symlink => "symlink",
+            Mode::Copy => "copy",
+            Mode::Hardlink => "hardlink",
+            Mode::Template => "template",
+            Mode::Secret => "secret",
         };
-        let current_hostname = sys.nodename().to_string_lossy();
-        let mut folders: Vec<PathBuf> = vec![];
-        let current_os = format!("{OS}-{ARCH}");
-
-        for program in self.config {
-            if program.os.is_none() && program.hostname.is_none() {
-                folders.append(&mut vec![program.folder]);
-            } else if program.os.is_none() {
-                let targethost = program.hostname;
-                if let Some(target) = targethost {
-                    match target {
-                        Hostname::Single(host) => {
-                            if host == current_hostname {
-                                folders.append(&mut vec![program.folder]);
-                            }
-                        }
-                        Hostname::Multiple(hosts) => {
-                            if hosts.contains(&current_hostname.to_string()) {
-                                folders.append(&mut vec![program.folder]);
-                            }
-                        }
-                    }
-                }
-            } else if program.hostname.is_none() {
-                let targetos = program.os;
-                if let Some(targetos) = targetos {
-                    if current_os == targetos {
-                        folders.append(&mut vec![program.folder]);
-                    }
-                }
-            } else {
-                let targetos = program.os;
-                let targethost = program.hostname;
-                if let Some(targetos) = targetos {
-                    if let Some(target) = targethost {
-                        if current_os == targetos {
-                            match target {
-                                Hostname::Single(host) => {
-                                    if host == current_hostname {
-                                        folders.append(&mut vec![program.folder]);
-                                    }
-                                }
-                                Hostname::Multiple(hosts) => {
-                                    if hosts.contains(&current_hostname.to_string()) {
-                                        folders.append(&mut vec![program.folder]);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+        write!(f, "{mode}")
+    }
+}
+
+// untagged enums swallow the error of each variant, so patterns are only
+// compiled once the shape is known to keep invalid globs and regexes readable
+impl JsonSchema for Hostname {
+    fn schema_name() -> String {
+        "Hostname".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some(
+                    "A hostname, glob, /regex/ or !negated pattern, or a list of them".to_string(),
+                ),
+                ..Default::default()
+            })),
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(vec![
+                    gen.subschema_for::<String>(),
+                    gen.subschema_for::<Vec<String>>(),
+                ]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl<'de> Deserialize<'de> for Hostname {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Single(String),
+            Multiple(Vec<String>),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Single(pattern) => HostPattern::try_from(pattern).map(Hostname::Single),
+            Raw::Multiple(patterns) => patterns
+                .into_iter()
+                .map(HostPattern::try_from)
+                .collect::<Result<_, _>>()
+                .map(Hostname::Multiple),
+        }
+        .map_err(de::Error::custom)
+    }
+}
+
+/// A config value written either as a bare string or as a map. Unlike an
+/// untagged enum it hands the map straight to `M`, so a typo in a key still
+/// reports the unknown field instead of "data did not match any variant".
+pub(crate) enum StringOrMap<M> {
+    String(String),
+    Map(M),
+}
+
+impl<'de, M: Deserialize<'de>> Deserialize<'de> for StringOrMap<M> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RawVisitor<M>(PhantomData<M>);
+
+        impl<'de, M: Deserialize<'de>> Visitor<'de> for RawVisitor<M> {
+            type Value = StringOrMap<M>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string or a map")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                Ok(StringOrMap::String(value.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                M::deserialize(MapAccessDeserializer::new(map)).map(StringOrMap::Map)
             }
         }
-        folders.dedup();
+
+        deserializer.deserialize_any(RawVisitor(PhantomData))
+    }
+}
+
+impl<'de> Deserialize<'de> for Include {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match StringOrMap::deserialize(deserializer)? {
+            StringOrMap::String(path) => Include::Path(path.into()),
+            StringOrMap::Map(include) => Include::Conditional(Box::new(include)),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Hook {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Detailed {
+            run: String,
+            #[serde(default)]
+            when: When,
+        }
+
+        Ok(match StringOrMap::<Detailed>::deserialize(deserializer)? {
+            StringOrMap::String(run) => Hook::Command(run),
+            StringOrMap::Map(Detailed { run, when }) => Hook::Detailed { run, when },
+        })
+    }
+}
+
+impl TryFrom<String> for HostPattern {
+    type Error = String;
+
+    fn try_from(raw: String) -> Result<Self, Self::Error> {
+        let (negated, pattern) = match raw.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, raw.as_str()),
+        };
+        let matcher = if let Some(regex) = pattern
+            .strip_prefix('/')
+            .and_then(|pattern| pattern.strip_suffix('/'))
+        {
+            Matcher::Regex(
+                Regex::new(regex).map_err(|e| format!("invalid hostname regex {raw}: {e}"))?,
+            )
+        } else if pattern.contains(['*', '?', '[', '{']) {
+            Matcher::Glob(
+                Glob::new(pattern)
+                    .map_err(|e| format!("invalid hostname glob {raw}: {e}"))?
+                    .compile_matcher(),
+            )
+        } else {
+            Matcher::Exact(pattern.to_string())
+        };
+        Ok(Self {
+            raw,
+            negated,
+            matcher,
+        })
+    }
+}
+
+impl HostPattern {
+    /// Whether the pattern itself matches, ignoring negation.
+    fn is_match(&self, hostname: &str) -> bool {
+        match &self.matcher {
+            Matcher::Exact(host) => host == hostname,
+            Matcher::Glob(glob) => glob.is_match(hostname),
+            Matcher::Regex(regex) => regex.is_match(hostname),
+        }
+    }
+}
+
+impl Hostname {
+    fn patterns(&self) -> &[HostPattern] {
+        match self {
+            Hostname::Single(pattern) => std::slice::from_ref(pattern),
+            Hostname::Multiple(patterns) => patterns,
+        }
+    }
+
+    /// Matches when any plain pattern matches, or there are only negated
+    /// ones, and none of the negated patterns match.
+    pub fn matches(&self, hostname: &str) -> bool {
+        let (negated, patterns): (Vec<&HostPattern>, Vec<&HostPattern>) =
+            self.patterns().iter().partition(|pattern| pattern.negated);
+        let selected =
+            patterns.is_empty() || patterns.iter().any(|pattern| pattern.is_match(hostname));
+        selected && !negated.iter().any(|pattern| pattern.is_match(hostname))
+    }
+
+    pub fn describe(&self) -> String {
+        self.patterns()
+            .iter()
+            .map(|pattern| pattern.raw.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// The machine dotfox is running on, as matched against the config.
+#[derive(Debug)]
+pub struct Host {
+    pub hostname: String,
+    pub os: String,
+    pub distro: Option<String>,
+    pub username: String,
+    /// Active profiles, the saved ones unless overridden on the command line.
+    pub profiles: Vec<String>,
+}
+
+impl Host {
+    pub fn current() -> Result<Self> {
+        Ok(Self {
+            hostname: current_hostname()?,
+            os: current_os(),
+            distro: current_distro(),
+            username: whoami::username(),
+            profiles: load_profiles()?,
+        })
+    }
+
+    /// Replaces the saved profiles, unless `profiles` is empty.
+    pub fn with_profiles(mut self, profiles: &[String]) -> Self {
+        if !profiles.is_empty() {
+            self.profiles = profiles.to_vec();
+        }
+        self
+    }
+
+    /// Entries without profiles are always active.
+    fn in_profile(&self, profiles: &[String]) -> bool {
+        profiles.is_empty()
+            || profiles
+                .iter()
+                .any(|profile| self.profiles.contains(profile))
+    }
+
+    /// Whether an entry restricted to `os`, `hostname` and `when` applies
+    /// here, an unset restriction matches everything.
+    fn selects(
+        &self,
+        os: &Option<String>,
+        hostname: &Option<Hostname>,
+        when: &Option<Condition>,
+    ) -> bool {
+        let os = match os {
+            Some(os) => *os == self.os,
+            None => true,
+        };
+        let hostname = match hostname {
+            Some(hostname) => hostname.matches(&self.hostname),
+            None => true,
+        };
+        let when = match when {
+            Some(when) => when.matches(self),
+            None => true,
+        };
+        os && hostname && when
+    }
+}
+
+pub fn current_hostname() -> Result<String> {
+    let sys = match PlatformInfo::new() {
+        Ok(sys) => sys,
+        Err(e) => return Err(anyhow!(e.to_string())),
+    };
+    Ok(sys.nodename().to_string_lossy().to_string())
+}
+
+/// The `OS-ARCH` string matched against `os` in the config.
+pub fn current_os() -> String {
+    format!("{OS}-{ARCH}")
+}
+
+/// `ID` from `/etc/os-release`, `None` outside of linux.
+pub fn current_distro() -> Option<String> {
+    let release = read_to_string("/etc/os-release").ok()?;
+    release
+        .lines()
+        .find_map(|line| line.strip_prefix("ID="))
+        .map(|id| id.trim_matches(['"', '\'']).to_string())
+}
+
+impl Secrets {
+    /// Recipients and identities add up, the backend and passphrase of
+    /// `other` win when it sets them.
+    fn merge(&mut self, other: Secrets) {
+        if other.backend != Backend::default() {
+            self.backend = other.backend;
+        }
+        self.passphrase |= other.passphrase;
+        self.recipients.extend(other.recipients);
+        self.identities.extend(other.identities);
+    }
+}
+
+impl Hooks {
+    fn merge(&mut self, other: Hooks) {
+        self.pre_sync.extend(other.pre_sync);
+        self.post_sync.extend(other.post_sync);
+        self.post_pull.extend(other.post_pull);
+        self.post_clone.extend(other.post_clone);
+    }
+
+    pub fn get(&self, event: Event) -> &[Hook] {
+        match event {
+            Event::PreSync => &self.pre_sync,
+            Event::PostSync => &self.post_sync,
+            Event::PostPull => &self.post_pull,
+            Event::PostClone => &self.post_clone,
+        }
+    }
+}
+
+impl Hook {
+    pub fn command(&self) -> &str {
+        match self {
+            Hook::Command(run) | Hook::Detailed { run, .. } => run,
+        }
+    }
+
+    pub fn when(&self) -> When {
+        match self {
+            Hook::Command(_) => When::Always,
+            Hook::Detailed { when, .. } => *when,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    PreSync,
+    PostSync,
+    PostPull,
+    PostClone,
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let event = match self {
+            Event::PreSync => "pre_sync",
+            Event::PostSync => "post_sync",
+            Event::PostPull => "post_pull",
+            Event::PostClone => "post_clone",
+        };
+        write!(f, "{event}")
+    }
+}
+
+impl Include {
+    pub fn path(&self) -> &Path {
+        match self {
+            Include::Path(path) => path,
+            Include::Conditional(include) => &include.path,
+        }
+    }
+
+    pub fn matches(&self, host: &Host) -> bool {
+        match self {
+            Include::Path(_) => true,
+            Include::Conditional(include) => {
+                host.selects(&include.os, &include.hostname, &include.when)
+            }
+        }
+    }
+}
+
+impl Override {
+    pub fn matches(&self, host: &Host) -> bool {
+        host.selects(&self.os, &self.hostname, &self.when)
+    }
+
+    /// Short description of what the override matches, for `dotfox vars`.
+    pub fn describe(&self) -> String {
+        let mut parts: Vec<String> = vec![];
+        if let Some(os) = &self.os {
+            parts.push(format!("os={os}"));
+        }
+        if let Some(hostname) = &self.hostname {
+            parts.push(format!("hostname={}", hostname.describe()));
+        }
+        if let Some(when) = &self.when {
+            parts.push(format!("when={when}"));
+        }
+        parts.join(" ")
+    }
+}
+
+impl Config {
+    /// Layers `other` on top of this config, its variables win and every list
+    /// is appended to.
+    pub fn merge(&mut self, other: Config) {
+        self.include.extend(other.include);
+        self.config.extend(other.config);
+        self.ignore.extend(other.ignore);
+        self.variables.extend(other.variables);
+        self.overrides.extend(other.overrides);
+        self.secrets.merge(other.secrets);
+        self.hooks.merge(other.hooks);
+    }
+
+    pub fn folders(self, host: &Host) -> Result<Vec<Programs>> {
+        let mut folders: Vec<Programs> = self
+            .config
+            .into_iter()
+            .filter(|program| host.selects(&program.os, &program.hostname, &program.when))
+            .filter(|program| host.in_profile(&program.profiles))
+            .collect();
+
+        folders.sort_by(|a, b| a.folder.cmp(&b.folder));
+        folders.dedup_by(|a, b| a.folder == b.folder);
         Ok(folders)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hostname(json: &str) -> Hostname {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn error(json: &str) -> String {
+        serde_json::from_str::<Hostname>(json)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn exact_hostnames_match_only_themselves() {
+        let single = hostname(r#""laptop""#);
+        assert!(single.matches("laptop"));
+        assert!(!single.matches("laptop-2"));
+        assert!(!single.matches("lap"));
+
+        let list = hostname(r#"["laptop", "desktop"]"#);
+        assert!(list.matches("laptop"));
+        assert!(list.matches("desktop"));
+        assert!(!list.matches("server"));
+    }
+
+    #[test]
+    fn globs_and_regexes_match_patterns() {
+        let glob = hostname(r#""work-*""#);
+        assert!(glob.matches("work-laptop"));
+        assert!(!glob.matches("home-laptop"));
+
+        let regex = hostname(r#""/^(pi|nas)[0-9]+$/""#);
+        assert!(regex.matches("pi4"));
+        assert!(regex.matches("nas12"));
+        assert!(!regex.matches("pi"));
+        assert!(!regex.matches("mypi4"));
+
+        // only a leading and trailing slash make a regex
+        let exact = hostname(r#""/srv""#);
+        assert!(exact.matches("/srv"));
+    }
+
+    #[test]
+    fn negation_excludes_from_the_other_patterns() {
+        let list = hostname(r#"["work-*", "!work-build"]"#);
+        assert!(list.matches("work-laptop"));
+        assert!(!list.matches("work-build"));
+        assert!(!list.matches("home"));
+
+        let regex = hostname(r#"["!/^ci-/"]"#);
+        assert!(!regex.matches("ci-runner"));
+        assert!(regex.matches("laptop"));
+    }
+
+    #[test]
+    fn only_negated_patterns_select_everything_else() {
+        let single = hostname(r#""!server""#);
+        assert!(single.matches("laptop"));
+        assert!(!single.matches("server"));
+
+        let list = hostname(r#"["!server", "!nas*"]"#);
+        assert!(list.matches("laptop"));
+        assert!(!list.matches("server"));
+        assert!(!list.matches("nas2"));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected_with_the_pattern() {
+        let regex = error(r#""/work-(/""#);
+        assert!(regex.contains("invalid hostname regex /work-(/"), "{regex}");
+
+        let glob = error(r#"["laptop", "work-[a"]"#);
+        assert!(glob.contains("invalid hostname glob work-[a"), "{glob}");
+
+        let negated = error(r#""!/(/""#);
+        assert!(negated.contains("invalid hostname regex !/(/"), "{negated}");
+    }
+
+    #[test]
+    fn describe_keeps_the_raw_patterns() {
+        assert_eq!(
+            hostname(r#"["work-*", "!/^ci-/"]"#).describe(),
+            "work-*,!/^ci-/"
+        );
+    }
+
+    #[test]
+    fn strings_or_maps_report_unknown_fields() {
+        let include = serde_json::from_str::<Include>(r#"{"path": "a.json", "hostnmae": "x"}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(include.contains("unknown field `hostnmae`"), "{include}");
+
+        let hook = serde_json::from_str::<Hook>(r#"{"run": "make", "whn": "once"}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(hook.contains("unknown field `whn`"), "{hook}");
+
+        let hook = serde_json::from_str::<Hook>(r#"{"run": "make", "when": "once"}"#).unwrap();
+        assert_eq!(hook.when(), When::Once);
+        assert!(matches!(
+            serde_json::from_str::<Include>(r#""a.json""#).unwrap(),
+            Include::Path(_)
+        ));
+    }
+}
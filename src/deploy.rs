@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{
+        copy, create_dir_all, hard_link, read_dir, remove_dir, remove_file, set_permissions, write,
+        File, OpenOptions, Permissions,
+    },
+    io::{self, Write},
+    os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+/// Copies `source` to `target`, recursing into directories.
+pub fn copy_recursive(source: &Path, target: &Path) -> Result<()> {
+    if source.is_dir() {
+        create_dir_all(target).context(format!("unable to create {}", target.display()))?;
+        for entry in read_dir(source)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &target.join(entry.file_name()))?;
+        }
+    } else {
+        copy(source, target).context(format!(
+            "unable to copy {} to {}",
+            source.display(),
+            target.display()
+        ))?;
+    }
+    Ok(())
+}
+
+/// Hardlinks `source` to `target`. Directories can't be hardlinked, so they
+/// are recreated and every file inside is linked instead.
+pub fn hardlink_recursive(source: &Path, target: &Path) -> Result<()> {
+    if source.is_dir() {
+        create_dir_all(target).context(format!("unable to create {}", target.display()))?;
+        for entry in read_dir(source)? {
+            let entry = entry?;
+            hardlink_recursive(&entry.path(), &target.join(entry.file_name()))?;
+        }
+    } else {
+        hard_link(source, target).context(format!(
+            "unable to hardlink {} to {}",
+            source.display(),
+            target.display()
+        ))?;
+    }
+    Ok(())
+}
+
+/// Removes what copying or hardlinking `source` put at `target`, file by
+/// file. Files are only removed while they still match their source, or are
+/// still `hardlinked` to it, and directories once they are empty, so anything
+/// added or changed since is kept. Returns the kept paths.
+pub fn remove_deployed(source: &Path, target: &Path, hardlinked: bool) -> Result<Vec<PathBuf>> {
+    let mut kept = vec![];
+
+    if source.is_dir() && target.is_dir() && !target.is_symlink() {
+        for entry in read_dir(source)? {
+            let entry = entry?;
+            let target = target.join(entry.file_name());
+            if target.exists() || target.is_symlink() {
+                kept.append(&mut remove_deployed(&entry.path(), &target, hardlinked)?);
+            }
+        }
+        if read_dir(target)?.next().is_some() {
+            kept.push(target.to_path_buf());
+        } else {
+            remove_dir(target).context(format!("unable to remove {}", target.display()))?;
+        }
+        return Ok(kept);
+    }
+
+    let matches = !target.is_symlink()
+        && if hardlinked {
+            same_inodes(source, target)
+        } else {
+            match (content_hash(source), content_hash(target)) {
+                (Ok(source), Ok(target)) => source == target,
+                _ => false,
+            }
+        };
+    if matches {
+        remove_file(target).context(format!("unable to remove {}", target.display()))?;
+    } else {
+        kept.push(target.to_path_buf());
+    }
+    Ok(kept)
+}
+
+/// Writes `content` generated from `source` to `target`, keeping the
+/// permissions of `source` so executable templates stay executable.
+pub fn write_generated(source: &Path, target: &Path, content: &[u8]) -> Result<()> {
+    write(target, content).context(format!("unable to write {}", target.display()))?;
+    set_permissions(target, source.metadata()?.permissions())?;
+    Ok(())
+}
+
+/// Writes decrypted `content` to `target`, readable by the owner only.
+pub fn write_private(target: &Path, content: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(target)
+        .context(format!("unable to write {}", target.display()))?;
+    // mode only applies to new files
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.write_all(content)?;
+    Ok(())
+}
+
+fn hash_into(path: &Path, hasher: &mut Sha256) -> Result<()> {
+    if path.is_dir() {
+        let mut entries: Vec<_> = read_dir(path)?.collect::<io::Result<_>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            hasher.update(entry.file_name().as_encoded_bytes());
+            hash_into(&entry.path(), hasher)?;
+        }
+    } else {
+        let mut file = File::open(path).context(format!("unable to read {}", path.display()))?;
+        io::copy(&mut file, hasher)?;
+    }
+    Ok(())
+}
+
+/// sha256 over the contents of `path`, including file names when it is a
+/// directory.
+pub fn content_hash(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hash_into(path, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Whether every file under `target` is a hardlink of the matching file
+/// under `source`.
+pub fn same_inodes(source: &Path, target: &Path) -> bool {
+    if source.is_dir() {
+        if !target.is_dir() || target.is_symlink() {
+            return false;
+        }
+        match read_dir(source) {
+            Ok(entries) => entries
+                .flatten()
+                .all(|entry| same_inodes(&entry.path(), &target.join(entry.file_name()))),
+            Err(_) => false,
+        }
+    } else {
+        match (source.metadata(), target.symlink_metadata()) {
+            (Ok(source), Ok(target)) => {
+                source.dev() == target.dev() && source.ino() == target.ino()
+            }
+            _ => false,
+        }
+    }
+}
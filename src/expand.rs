@@ -0,0 +1,68 @@
+use anyhow::{anyhow, Context, Result};
+use dirs::{cache_dir, config_dir, data_dir, executable_dir, home_dir, state_dir};
+use std::{env, path::PathBuf};
+
+/// Value of an environment variable. The xdg base dir variables fall back to
+/// their platform default when unset, so `$XDG_CONFIG_HOME/foo` always works.
+pub fn env_var(name: &str) -> Option<String> {
+    if let Ok(value) = env::var(name) {
+        return Some(value);
+    }
+    let dir = match name {
+        "HOME" => home_dir(),
+        "XDG_CONFIG_HOME" => config_dir(),
+        "XDG_CACHE_HOME" => cache_dir(),
+        "XDG_DATA_HOME" => data_dir(),
+        "XDG_STATE_HOME" => state_dir(),
+        "XDG_BIN_HOME" => executable_dir(),
+        _ => None,
+    };
+    dir.map(|dir| dir.to_string_lossy().to_string())
+}
+
+/// Expands a leading `~` as well as `$VAR` and `${VAR}` using `lookup`.
+pub fn expand(input: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+    let mut output = String::new();
+    let mut rest = input;
+
+    if rest == "~" || rest.starts_with("~/") {
+        let home = home_dir().context("unable to resolve home directory")?;
+        output.push_str(&home.to_string_lossy());
+        rest = &rest[1..];
+    }
+
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let (name, remainder) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| anyhow!("unterminated ${{ in {input}"))?;
+            (&braced[..end], &braced[end + 1..])
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+
+        if name.is_empty() {
+            output.push('$');
+        } else {
+            let value = lookup(name).ok_or_else(|| anyhow!("{name} is not set in {input}"))?;
+            output.push_str(&value);
+        }
+        rest = remainder;
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Expands `path` against `lookup`. Relative results are taken to be
+/// relative to `$HOME`.
+pub fn expand_path(path: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<PathBuf> {
+    let expanded = PathBuf::from(expand(path, lookup)?);
+    let home = home_dir().context("unable to resolve home directory")?;
+    Ok(home.join(expanded))
+}
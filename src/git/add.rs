@@ -1,5 +1,6 @@
 use anyhow::{Context, Result};
 use git2::{IndexAddOption, Repository};
+use std::path::Path;
 
 pub fn git_add(repo: &Repository) -> Result<()> {
     let mut index = repo.index().context("Failed to get index of repo")?;
@@ -10,3 +11,14 @@ pub fn git_add(repo: &Repository) -> Result<()> {
     index.write()?;
     Ok(())
 }
+
+/// Stages a single `path`, relative to the repo's workdir.
+pub fn git_add_path(repo: &Repository, path: &Path) -> Result<()> {
+    let mut index = repo.index().context("Failed to get index of repo")?;
+
+    index
+        .add_path(path)
+        .context(format!("Failed to add {} to repo", path.display()))?;
+    index.write()?;
+    Ok(())
+}
@@ -3,3 +3,4 @@ pub mod commit;
 pub mod pull;
 pub mod push;
 pub mod shared;
+pub mod status;
@@ -0,0 +1,77 @@
+use anyhow::{Context, Result};
+use git2::{BranchType, Repository, Status, StatusOptions};
+
+/// Two letter code like `git status --short`, index then worktree.
+fn short_status(status: Status) -> String {
+    if status.contains(Status::WT_NEW) {
+        return "??".to_string();
+    }
+    let index = if status.contains(Status::INDEX_NEW) {
+        'A'
+    } else if status.contains(Status::INDEX_MODIFIED) {
+        'M'
+    } else if status.contains(Status::INDEX_DELETED) {
+        'D'
+    } else if status.contains(Status::INDEX_RENAMED) {
+        'R'
+    } else if status.contains(Status::INDEX_TYPECHANGE) {
+        'T'
+    } else {
+        ' '
+    };
+    let worktree = if status.contains(Status::WT_MODIFIED) {
+        'M'
+    } else if status.contains(Status::WT_DELETED) {
+        'D'
+    } else if status.contains(Status::WT_RENAMED) {
+        'R'
+    } else if status.contains(Status::WT_TYPECHANGE) {
+        'T'
+    } else if status.contains(Status::CONFLICTED) {
+        'U'
+    } else {
+        ' '
+    };
+    format!("{index}{worktree}")
+}
+
+/// Uncommitted changes as path and short status, untracked files included.
+pub fn changes(repo: &Repository) -> Result<Vec<(String, String)>> {
+    let mut status_opts = StatusOptions::new();
+    status_opts
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(false);
+
+    let statuses = repo
+        .statuses(Some(&mut status_opts))
+        .context("Failed to get status of repo")?;
+    Ok(statuses
+        .iter()
+        .map(|entry| {
+            let path = entry.path().unwrap_or_default().to_string();
+            (path, short_status(entry.status()))
+        })
+        .collect())
+}
+
+/// The upstream of the current branch with how many commits the branch is
+/// ahead and behind it, as of the last fetch. `None` without an upstream.
+pub fn ahead_behind(repo: &Repository) -> Result<Option<(String, usize, usize)>> {
+    // an unborn branch has no head yet
+    let Ok(head) = repo.head() else {
+        return Ok(None);
+    };
+    let Some(name) = head.shorthand().filter(|_| head.is_branch()) else {
+        return Ok(None);
+    };
+    let Ok(upstream) = repo.find_branch(name, BranchType::Local)?.upstream() else {
+        return Ok(None);
+    };
+
+    let local = head.target().context("branch has no commits")?;
+    let remote = upstream.get().target().context("upstream has no commits")?;
+    let (ahead, behind) = repo.graph_ahead_behind(local, remote)?;
+    let upstream = upstream.name()?.unwrap_or_default().to_string();
+    Ok(Some((upstream, ahead, behind)))
+}
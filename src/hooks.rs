@@ -0,0 +1,180 @@
+use super::{
+    config::{Event, Hook, Host, When},
+    deploy::content_hash,
+    resolve::{load_config, matched_folders},
+    state::{state_dir, timestamp},
+    utils::print_info,
+    variables::{lookup, resolve_variables, values},
+};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{create_dir_all, File},
+    io::stderr,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const HOOKS: &str = "hooks.json";
+
+/// The last time a hook ran, and for `on_change` hooks the hash of its
+/// folders at that point.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Run {
+    repo: PathBuf,
+    folder: Option<PathBuf>,
+    event: String,
+    command: String,
+    hash: String,
+    timestamp: u64,
+}
+
+/// Hooks that have run on this machine, across all repos.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HookState {
+    runs: Vec<Run>,
+}
+
+impl HookState {
+    fn load() -> Result<Self> {
+        let path = state_dir()?.join(HOOKS);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let reader = File::open(&path).context(format!("unable to read {}", path.display()))?;
+        serde_json::from_reader(reader).context(format!("{} is corrupt", path.display()))
+    }
+
+    fn save(&self) -> Result<()> {
+        let dir = state_dir()?;
+        create_dir_all(&dir).context(format!("unable to create {}", dir.display()))?;
+        let path = dir.join(HOOKS);
+        let writer = File::create(&path).context(format!("unable to write {}", path.display()))?;
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    fn find(&self, run: &Run) -> Option<&Run> {
+        self.runs.iter().find(|other| {
+            other.repo == run.repo
+                && other.folder == run.folder
+                && other.event == run.event
+                && other.command == run.command
+        })
+    }
+
+    fn record(&mut self, run: Run) {
+        self.runs.retain(|other| {
+            !(other.repo == run.repo
+                && other.folder == run.folder
+                && other.event == run.event
+                && other.command == run.command)
+        });
+        self.runs.push(run);
+    }
+}
+
+/// sha256 over the content of every dir in `dirs`.
+fn folders_hash(dirs: &[&Path]) -> Result<String> {
+    let mut hasher = Sha256::new();
+    for dir in dirs {
+        hasher.update(content_hash(dir)?);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Runs `hook` through `sh -c` inside `dir`. Its output goes to stderr like
+/// every other log, keeping stdout for the results of the command.
+fn run_hook(hook: &Hook, dir: &Path, env: &[(&str, String)]) -> Result<()> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(hook.command())
+        .current_dir(dir)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdout(stderr())
+        .status()
+        .context(format!("unable to run hook {}", hook.command()))?;
+    if !status.success() {
+        return Err(anyhow!("hook {} failed with {status}", hook.command()));
+    }
+    Ok(())
+}
+
+/// Runs the global hooks for `event` followed by the ones of every folder that
+/// applies to this machine, as documented on `config::Hooks` and
+/// `config::When`. With `dry_run` the hooks that would run are only printed.
+pub fn run_hooks(path: &Path, host: &Host, event: Event, dry_run: bool) -> Result<()> {
+    let repo = path.canonicalize()?;
+    let config = load_config(&repo, host)?;
+    let global = config.hooks.get(event).to_vec();
+    let variables = values(&resolve_variables(&config, host)?);
+    let folders = matched_folders(&repo, config, host, &lookup(&variables))?;
+
+    let mut hooks: Vec<(&Hook, Option<&Path>)> = global.iter().map(|hook| (hook, None)).collect();
+    for (dir, program) in &folders {
+        hooks.extend(
+            program
+                .hooks
+                .get(event)
+                .iter()
+                .map(|hook| (hook, Some(dir.as_path()))),
+        );
+    }
+    if hooks.is_empty() {
+        return Ok(());
+    }
+
+    let dirs: Vec<&Path> = folders.iter().map(|(dir, _)| dir.as_path()).collect();
+    let joined = dirs
+        .iter()
+        .map(|dir| dir.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(":");
+    let mut state = HookState::load()?;
+
+    for (hook, folder) in hooks {
+        let hash = match (hook.when(), folder) {
+            (When::OnChange, Some(folder)) => content_hash(folder)?,
+            (When::OnChange, None) => folders_hash(&dirs)?,
+            _ => String::new(),
+        };
+        let run = Run {
+            repo: repo.clone(),
+            folder: folder.map(Path::to_path_buf),
+            event: event.to_string(),
+            command: hook.command().to_string(),
+            hash,
+            timestamp: timestamp(),
+        };
+        let skip = match (hook.when(), state.find(&run)) {
+            (When::Always, _) | (_, None) => false,
+            (When::Once, Some(_)) => true,
+            (When::OnChange, Some(last)) => last.hash == run.hash,
+        };
+        if skip {
+            continue;
+        }
+        if dry_run {
+            print_info(format!("would run {event} hook: {}", hook.command()));
+            continue;
+        }
+
+        print_info(format!("running {event} hook: {}", hook.command()));
+        let mut env = vec![
+            ("DOTFOX_EVENT", event.to_string()),
+            ("DOTFOX_REPO", repo.to_string_lossy().to_string()),
+            ("DOTFOX_HOSTNAME", host.hostname.clone()),
+            ("DOTFOX_OS", host.os.clone()),
+            ("DOTFOX_FOLDERS", joined.clone()),
+        ];
+        if let Some(folder) = folder {
+            env.push(("DOTFOX_FOLDER", folder.to_string_lossy().to_string()));
+        }
+        run_hook(hook, folder.unwrap_or(&repo), &env)?;
+
+        state.record(run);
+        state.save()?;
+    }
+    Ok(())
+}
@@ -1,14 +1,33 @@
 use anyhow::{anyhow, Result};
 use clap::Parser;
-use cli::Commands;
+use cli::{Commands, ConfigCommands, SyncArgs};
+use config::{Event, Host};
 use git2::Repository;
+use hooks::run_hooks;
 use std::{fs::create_dir_all, path::PathBuf, process::exit};
-use utils::{clone, commit, print_error, print_info, pull, push, sync, verify};
+use utils::{
+    clone, commit, encrypt, print_error, print_info, profile, prune, pull, push, restore_backups,
+    schema, status, sync, unsync, validate, vars, verify,
+};
+mod backup;
 mod cli;
+mod condition;
 mod config;
+mod deploy;
+mod expand;
 mod git;
+mod hooks;
+mod manifest;
 mod map;
+mod output;
+mod plan;
+mod prune;
+mod resolve;
+mod secrets;
+mod state;
+mod template;
 mod utils;
+mod variables;
 
 fn resolve_dir(path: Option<PathBuf>) -> Result<PathBuf> {
     match path {
@@ -51,13 +70,24 @@ fn main() -> Result<()> {
             Ok(())
         }
 
-        Commands::Sync { path } => {
+        Commands::Sync { args, path } => {
             let path = resolve_dir(path)?;
-            sync(&path)?;
+            sync(&path, &args, cli.output)?;
             Ok(())
         }
 
-        Commands::Clone { url, path } => {
+        Commands::Plan { profile, path } => {
+            let path = resolve_dir(path)?;
+            let args = SyncArgs {
+                dry_run: true,
+                profile,
+                ..Default::default()
+            };
+            sync(&path, &args, cli.output)?;
+            Ok(())
+        }
+
+        Commands::Clone { url, profile, path } => {
             let path = match path {
                 Some(path) => path,
                 None => {
@@ -72,7 +102,13 @@ fn main() -> Result<()> {
                 }
             };
             clone(url, &path)?;
-            sync(&path)?;
+            let args = SyncArgs {
+                profile,
+                ..Default::default()
+            };
+            sync(&path, &args, cli.output)?;
+            let host = Host::current()?.with_profiles(&args.profile);
+            run_hooks(&path, &host, Event::PostClone, false)?;
             Ok(())
         }
 
@@ -87,15 +123,83 @@ fn main() -> Result<()> {
             push(&path, message)?;
             Ok(())
         }
-        Commands::Pull { path } => {
+        Commands::Pull { profile, path } => {
             let path = resolve_dir(path)?;
             pull(&path)?;
-            sync(&path)?;
+            let args = SyncArgs {
+                profile,
+                ..Default::default()
+            };
+            sync(&path, &args, cli.output)?;
+            let host = Host::current()?.with_profiles(&args.profile);
+            run_hooks(&path, &host, Event::PostPull, false)?;
+            Ok(())
+        }
+        Commands::Unsync {
+            restore_backups,
+            profile,
+            path,
+        } => {
+            let path = resolve_dir(path)?;
+            unsync(&path, restore_backups, &profile)?;
+            Ok(())
+        }
+        Commands::RestoreBackups { path } => {
+            let path = resolve_dir(path)?;
+            restore_backups(&path)?;
+            Ok(())
+        }
+        Commands::Prune { yes, profile, path } => {
+            let path = resolve_dir(path)?;
+            prune(&path, yes, &profile)?;
+            Ok(())
+        }
+        Commands::Vars { profile, path } => {
+            let path = resolve_dir(path)?;
+            vars(&path, &profile, cli.output)?;
+            Ok(())
+        }
+        Commands::Verify {
+            fix,
+            on_conflict,
+            profile,
+            path,
+        } => {
+            let path = resolve_dir(path)?;
+            let code = verify(&path, fix, on_conflict, &profile, cli.output)?;
+            if code != 0 {
+                exit(code);
+            }
+            Ok(())
+        }
+        Commands::Status { profile, path } => {
+            let path = resolve_dir(path)?;
+            status(&path, &profile, cli.output)?;
+            Ok(())
+        }
+        Commands::Config { command } => match command {
+            ConfigCommands::Validate { path } => {
+                let path = resolve_dir(path)?;
+                validate(&path)?;
+                Ok(())
+            }
+            ConfigCommands::Schema { output } => {
+                schema(output)?;
+                Ok(())
+            }
+        },
+        Commands::Profile { set, clear } => {
+            profile(&set, clear)?;
             Ok(())
         }
-        Commands::Verify { path } => {
+        Commands::Encrypt {
+            file,
+            output,
+            armor,
+            path,
+        } => {
             let path = resolve_dir(path)?;
-            verify(&path)?;
+            encrypt(&path, &file, output, armor)?;
             Ok(())
         }
     }
@@ -0,0 +1,186 @@
+use super::{
+    config::Mode,
+    deploy::content_hash,
+    plan::{plan_link, points_into, Action},
+    resolve::Link,
+    state::{state_dir, timestamp},
+};
+use anyhow::{Context, Result};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{create_dir_all, File},
+    path::{Path, PathBuf},
+};
+
+const MANIFEST: &str = "manifest.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    Symlink,
+    Copy,
+    Hardlink,
+    Template,
+    Secret,
+}
+
+impl From<Mode> for LinkKind {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Symlink => LinkKind::Symlink,
+            Mode::Copy => LinkKind::Copy,
+            Mode::Hardlink => LinkKind::Hardlink,
+            Mode::Template => LinkKind::Template,
+            Mode::Secret => LinkKind::Secret,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub kind: LinkKind,
+    pub commit: Option<String>,
+    pub timestamp: u64,
+    /// `content_hash` of the target right after it was deployed, for every
+    /// kind but symlinks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+impl Entry {
+    /// Whether the target is still exactly what dotfox put there, a symlink
+    /// to the source or content matching the recorded hash.
+    fn in_place(&self) -> bool {
+        match self.kind {
+            LinkKind::Symlink => {
+                self.target.is_symlink() && points_into(&self.target, &self.source)
+            }
+            _ => {
+                !self.target.is_symlink()
+                    && self.hash.as_ref().is_some_and(|hash| {
+                        content_hash(&self.target).is_ok_and(|current| current == *hash)
+                    })
+            }
+        }
+    }
+}
+
+/// Every link dotfox has created, across all repos synced on this machine.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub entries: Vec<Entry>,
+}
+
+fn head_commit(repo: &Path) -> Option<String> {
+    let repo = Repository::open(repo).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(commit.id().to_string())
+}
+
+impl Manifest {
+    pub fn load() -> Result<Self> {
+        let path = state_dir()?.join(MANIFEST);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let reader = File::open(&path).context(format!("unable to read {}", path.display()))?;
+        serde_json::from_reader(reader).context(format!("{} is corrupt", path.display()))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let dir = state_dir()?;
+        create_dir_all(&dir).context(format!("unable to create {}", dir.display()))?;
+        let path = dir.join(MANIFEST);
+        let writer = File::create(&path).context(format!("unable to write {}", path.display()))?;
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Entries owned by `repo` that the current config no longer produces, or
+    /// whose source is gone, and whose symlink is still in place. Copies are
+    /// never considered stale as they may have been edited since.
+    pub fn stale(&self, repo: &Path, files: &[Link]) -> Vec<&Entry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.source.starts_with(repo))
+            .filter(|entry| {
+                !entry.source.exists()
+                    || !files
+                        .iter()
+                        .any(|link| link.source == entry.source && link.target == entry.target)
+            })
+            .filter(|entry| entry.target.is_symlink() && points_into(&entry.target, repo))
+            .collect()
+    }
+
+    /// Replaces the entries owned by `repo` with the links from `files` that
+    /// are actually in place. Targets that aren't keep their previous entry,
+    /// so one changed by hand becomes owned again once it matches.
+    pub fn record(&mut self, repo: &Path, files: &[Link]) {
+        let commit = head_commit(repo);
+        let now = timestamp();
+        let (owned, mut entries): (Vec<Entry>, Vec<Entry>) = self
+            .entries
+            .drain(..)
+            .partition(|entry| entry.source.starts_with(repo));
+
+        for link in files {
+            if plan_link(link, true) != Action::Ok {
+                entries.extend(
+                    owned
+                        .iter()
+                        .filter(|entry| entry.target == link.target)
+                        .cloned(),
+                );
+                continue;
+            }
+            let kind = LinkKind::from(link.mode);
+            let previous = owned.iter().find(|entry| {
+                entry.source == link.source && entry.target == link.target && entry.kind == kind
+            });
+
+            let hash = match kind {
+                LinkKind::Symlink => None,
+                _ => content_hash(&link.target).ok(),
+            };
+
+            entries.push(match previous {
+                Some(entry) => Entry {
+                    hash,
+                    ..entry.clone()
+                },
+                None => Entry {
+                    source: link.source.clone(),
+                    target: link.target.clone(),
+                    kind,
+                    commit: commit.clone(),
+                    timestamp: now,
+                    hash,
+                },
+            });
+        }
+        self.entries = entries;
+    }
+
+    /// Whether dotfox put `target` in place and nothing changed it since, so
+    /// it can be replaced without going through the conflict policy.
+    pub fn owns(&self, target: &Path) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.target == target && entry.in_place())
+    }
+
+    /// Drops every entry owned by `repo`.
+    pub fn forget(&mut self, repo: &Path) {
+        self.entries.retain(|entry| !entry.source.starts_with(repo));
+    }
+
+    /// Drops the entries for `targets`, which dotfox no longer put there.
+    pub fn forget_targets(&mut self, targets: &[PathBuf]) {
+        self.entries
+            .retain(|entry| !targets.contains(&entry.target));
+    }
+}
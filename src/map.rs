@@ -1,17 +1,59 @@
+use crate::{
+    plan::{Action, LinkState, Step},
+    resolve::Link,
+    variables::Variable,
+};
+use serde::Serialize;
 use std::path::Path;
 use tabled::Tabled;
 
-#[derive(Debug, Tabled)]
+#[derive(Debug, Tabled, Serialize)]
 pub struct Map {
     source: String,
     target: String,
 }
 
-#[derive(Debug, Tabled)]
+#[derive(Debug, Tabled, Serialize)]
 pub struct VerifyMap {
     source: String,
     target: String,
-    tainted: bool,
+    state: LinkState,
+}
+
+#[derive(Debug, Tabled, Serialize)]
+pub struct FixMap {
+    source: String,
+    target: String,
+    before: LinkState,
+    after: LinkState,
+}
+
+#[derive(Debug, Tabled, Serialize)]
+pub struct PlanMap {
+    source: String,
+    target: String,
+    action: Action,
+}
+
+#[derive(Debug, Tabled, Serialize)]
+pub struct ResultMap {
+    source: String,
+    target: String,
+    action: Action,
+    state: LinkState,
+}
+
+#[derive(Debug, Tabled, Serialize)]
+pub struct StatusMap {
+    status: String,
+    path: String,
+}
+
+#[derive(Debug, Tabled, Serialize)]
+pub struct VarsMap {
+    name: String,
+    value: String,
+    source: String,
 }
 
 impl Map {
@@ -24,15 +66,62 @@ impl Map {
 }
 
 impl VerifyMap {
-    pub fn new(source: &Path, target: &Path) -> Self {
+    pub fn new(source: &Path, target: &Path, state: LinkState) -> Self {
         Self {
             source: format!("{}", source.display()),
             target: format!("{}", target.display()),
-            tainted: false,
+            state,
         }
     }
+}
 
-    pub fn taint(&mut self) {
-        self.tainted = true;
+impl FixMap {
+    pub fn new(link: &Link, before: LinkState, after: LinkState) -> Self {
+        Self {
+            source: format!("{}", link.source.display()),
+            target: format!("{}", link.target.display()),
+            before,
+            after,
+        }
+    }
+}
+
+impl PlanMap {
+    pub fn new(step: &Step) -> Self {
+        Self {
+            source: format!("{}", step.link.source.display()),
+            target: format!("{}", step.link.target.display()),
+            action: step.action,
+        }
+    }
+}
+
+impl VarsMap {
+    pub fn new(name: &str, variable: &Variable) -> Self {
+        Self {
+            name: name.to_string(),
+            value: variable.value.clone(),
+            source: variable.source.clone(),
+        }
+    }
+}
+
+impl ResultMap {
+    pub fn new(step: &Step, state: LinkState) -> Self {
+        Self {
+            source: format!("{}", step.link.source.display()),
+            target: format!("{}", step.link.target.display()),
+            action: step.action,
+            state,
+        }
+    }
+}
+
+impl StatusMap {
+    pub fn new(path: &str, status: &str) -> Self {
+        Self {
+            status: status.to_string(),
+            path: path.to_string(),
+        }
     }
 }
@@ -0,0 +1,59 @@
+use super::cli::Output;
+use anyhow::Result;
+use owo_colors::{OwoColorize, Stream::Stdout};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tabled::{Table, Tabled};
+
+/// What a command prints on stdout. Tables and plain rows are printed as
+/// they are added, json is collected into one object printed by `finish`.
+pub struct Report {
+    output: Output,
+    sections: Map<String, Value>,
+}
+
+impl Report {
+    pub fn new(output: Output) -> Self {
+        Self {
+            output,
+            sections: Map::new(),
+        }
+    }
+
+    /// Adds `rows` as a table, tab separated fields without a header for
+    /// plain output, or as the array `name` for json.
+    pub fn table<T: Tabled + Serialize>(&mut self, name: &str, rows: &[T]) -> Result<()> {
+        match self.output {
+            Output::Table => {
+                let table = Table::new(rows).to_string();
+                println!("{}", table.if_supports_color(Stdout, |text| text.bold()));
+            }
+            Output::Plain => {
+                for row in rows {
+                    println!("{}", row.fields().join("\t"));
+                }
+            }
+            Output::Json => {
+                self.sections
+                    .insert(name.to_string(), serde_json::to_value(rows)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds `value` as `name` for json, other outputs already log it.
+    pub fn value<T: Serialize>(&mut self, name: &str, value: &T) -> Result<()> {
+        if self.output == Output::Json {
+            self.sections
+                .insert(name.to_string(), serde_json::to_value(value)?);
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        if self.output == Output::Json {
+            println!("{}", serde_json::to_string_pretty(&self.sections)?);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+use super::{
+    config::Mode,
+    deploy::{content_hash, same_inodes},
+    manifest::{Entry, Manifest},
+    resolve::Link,
+};
+use serde::Serialize;
+use std::{
+    fmt::{self, Display},
+    fs::{read, read_link},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
+
+/// What `sync` would have to do to make `target` point at `source`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Create,
+    CreateParent,
+    Ok,
+    WrongLink,
+    Blocked,
+    Update,
+    Remove,
+    Undecryptable,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self {
+            Action::Create => "create link",
+            Action::CreateParent => "create parent dir and link",
+            Action::Ok => "up to date",
+            Action::WrongLink => "wrong link",
+            Action::Blocked => "real file in the way",
+            Action::Update => "replace outdated target",
+            Action::Remove => "remove stale link",
+            Action::Undecryptable => "unable to decrypt source",
+        };
+        write!(f, "{action}")
+    }
+}
+
+/// How a target compares to what the config wants there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkState {
+    Ok,
+    Missing,
+    /// A symlink whose destination is gone.
+    Dangling,
+    /// A file or directory that dotfox didn't put there, or changed since.
+    RealFile,
+    /// A symlink pointing somewhere other than the source.
+    ForeignLink,
+    /// A copy dotfox made that no longer matches its source.
+    Outdated,
+    /// A secret whose source can't be decrypted on this machine.
+    Undecryptable,
+}
+
+impl Display for LinkState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            LinkState::Ok => "ok",
+            LinkState::Missing => "missing",
+            LinkState::Dangling => "dangling",
+            LinkState::RealFile => "real file",
+            LinkState::ForeignLink => "foreign link",
+            LinkState::Outdated => "outdated",
+            LinkState::Undecryptable => "undecryptable",
+        };
+        write!(f, "{state}")
+    }
+}
+
+impl LinkState {
+    /// Bit set in the exit code of `dotfox verify` when a target is in this
+    /// state, so monitoring can tell every kind of problem apart.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LinkState::Ok => 0,
+            LinkState::Missing => 2,
+            LinkState::Dangling => 4,
+            LinkState::RealFile => 8,
+            LinkState::ForeignLink => 16,
+            LinkState::Outdated => 32,
+            LinkState::Undecryptable => 64,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Step {
+    pub link: Link,
+    pub action: Action,
+}
+
+fn is_current(link: &Link) -> bool {
+    let (source, target) = (&link.source, &link.target);
+    match link.mode {
+        Mode::Symlink => match (target.canonicalize(), source.canonicalize()) {
+            (Ok(target), Ok(source)) => target == source,
+            _ => false,
+        },
+        Mode::Copy => match (content_hash(source), content_hash(target)) {
+            (Ok(source), Ok(target)) => source == target,
+            _ => false,
+        },
+        Mode::Hardlink => same_inodes(source, target),
+        Mode::Template => match (link.content(), read(target)) {
+            (Ok(content), Ok(target)) => content == target,
+            _ => false,
+        },
+        Mode::Secret => {
+            let private = target
+                .metadata()
+                .is_ok_and(|metadata| metadata.permissions().mode() & 0o777 == 0o600);
+            match (link.content(), read(target)) {
+                (Ok(content), Ok(target)) => private && content == target,
+                _ => false,
+            }
+        }
+    }
+}
+
+/// `owned` targets were put in place by dotfox and haven't changed since, so
+/// an outdated copy or a target left over from another mode is replaced
+/// rather than reported. Anything else is blocked and left to the conflict
+/// policy.
+pub fn plan_link(link: &Link, owned: bool) -> Action {
+    let target = &link.target;
+
+    if link.mode == Mode::Secret && link.content().is_err() {
+        return Action::Undecryptable;
+    }
+
+    if !target.exists() && !target.is_symlink() {
+        return match target.parent() {
+            Some(parent) if !parent.is_dir() => Action::CreateParent,
+            _ => Action::Create,
+        };
+    }
+
+    let is_symlink = target.is_symlink();
+    if is_symlink == (link.mode == Mode::Symlink) && is_current(link) {
+        Action::Ok
+    } else if owned && !(is_symlink && link.mode == Mode::Symlink) {
+        Action::Update
+    } else if is_symlink {
+        Action::WrongLink
+    } else {
+        Action::Blocked
+    }
+}
+
+/// The state of `link`'s target. Only `owned` targets can be outdated,
+/// anything else that isn't what the config wants is a real file.
+pub fn link_state(link: &Link, owned: bool) -> LinkState {
+    let target = &link.target;
+    let is_symlink = target.is_symlink();
+
+    if link.mode == Mode::Secret && link.content().is_err() {
+        return LinkState::Undecryptable;
+    }
+    if !target.exists() {
+        return if is_symlink {
+            LinkState::Dangling
+        } else {
+            LinkState::Missing
+        };
+    }
+    match plan_link(link, owned) {
+        Action::Ok => LinkState::Ok,
+        Action::Undecryptable => LinkState::Undecryptable,
+        _ if is_symlink => LinkState::ForeignLink,
+        _ if owned && link.mode != Mode::Symlink => LinkState::Outdated,
+        _ => LinkState::RealFile,
+    }
+}
+
+/// Whether the symlink at `target` points somewhere inside `repo`, even if
+/// what it points at has since been deleted.
+pub fn points_into(target: &Path, repo: &Path) -> bool {
+    let link = match read_link(target) {
+        Ok(link) => link,
+        Err(_) => return false,
+    };
+    let link = match target.parent() {
+        Some(parent) => parent.join(link),
+        None => link,
+    };
+    let link = match (link.parent(), link.file_name()) {
+        (Some(parent), Some(name)) => match parent.canonicalize() {
+            Ok(parent) => parent.join(name),
+            Err(_) => link,
+        },
+        _ => link,
+    };
+    let repo = repo.canonicalize().unwrap_or_else(|_| repo.to_path_buf());
+
+    link.starts_with(repo)
+}
+
+pub fn plan(files: &[Link], manifest: &Manifest) -> Vec<Step> {
+    files
+        .iter()
+        .map(|link| Step {
+            link: link.clone(),
+            action: plan_link(link, manifest.owns(&link.target)),
+        })
+        .collect()
+}
+
+/// Puts the removal of `stale` links in front of `steps`, so a target that
+/// is being handed to a new source gets relinked instead of reported, and a
+/// linked directory being unfolded into per-file links is recreated.
+pub fn with_removals(steps: Vec<Step>, stale: &[&Entry]) -> Vec<Step> {
+    let mut removals: Vec<Step> = stale
+        .iter()
+        .map(|entry| Step {
+            link: Link::new(entry.source.clone(), entry.target.clone(), Mode::Symlink),
+            action: Action::Remove,
+        })
+        .collect();
+
+    for mut step in steps {
+        if step.action == Action::WrongLink
+            && stale.iter().any(|entry| entry.target == step.link.target)
+        {
+            step.action = Action::Create;
+        } else if stale.iter().any(|entry| {
+            step.link.target.starts_with(&entry.target) && step.link.target != entry.target
+        }) {
+            step.action = Action::CreateParent;
+        }
+        removals.push(step);
+    }
+    removals
+}
@@ -0,0 +1,45 @@
+use super::{
+    plan::points_into,
+    resolve::{xdg_dirs, Link},
+};
+use anyhow::{Context, Result};
+use dirs::home_dir;
+use std::{
+    collections::HashSet,
+    fs::{read_dir, read_link},
+    path::{Path, PathBuf},
+};
+
+/// Finds symlinks in `$HOME`, the xdg base dirs and next to every current
+/// target that point into `repo` but no longer belong to any resolved pair.
+/// Returns `(link destination, link)` pairs.
+pub fn find_stale_links(repo: &Path, files: &[Link]) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut dirs: Vec<PathBuf> = vec![home_dir().context("unable to resolve home directory")?];
+    dirs.extend(xdg_dirs());
+    dirs.extend(
+        files
+            .iter()
+            .filter_map(|link| link.target.parent().map(Path::to_path_buf)),
+    );
+    dirs.sort();
+    dirs.dedup();
+
+    let targets: HashSet<&PathBuf> = files.iter().map(|link| &link.target).collect();
+    let mut stale: Vec<(PathBuf, PathBuf)> = vec![];
+
+    for dir in dirs {
+        let entries = match read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let link = entry.path();
+            if !link.is_symlink() || targets.contains(&link) || !points_into(&link, repo) {
+                continue;
+            }
+            let source = read_link(&link)?;
+            stale.push((source, link));
+        }
+    }
+    Ok(stale)
+}
@@ -0,0 +1,480 @@
+use super::{
+    config::{Config, Granularity, Host, Mode, Programs, Secrets},
+    expand::{expand, expand_path},
+    secrets::{decrypt, is_secret},
+    template::{render, TEMPLATE_EXTENSION},
+    variables::{lookup, resolve_variables, values},
+};
+use anyhow::{anyhow, Context, Result};
+use dirs::{cache_dir, config_dir, data_dir, executable_dir, home_dir, state_dir};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    cell::OnceCell,
+    collections::BTreeMap,
+    fs::{read_dir, read_to_string},
+    path::{Path, PathBuf},
+};
+
+#[cfg(debug_assertions)]
+use super::utils::print_error;
+
+/// Config file names, only one of them may exist in a repo.
+pub const CONFIG_FILES: &[&str] = &["dotfox.json", "dotfox.toml", "dotfox.yaml"];
+
+/// The config file of the repo at `path`.
+pub fn config_path(path: &Path) -> Result<PathBuf> {
+    let found: Vec<PathBuf> = CONFIG_FILES
+        .iter()
+        .map(|name| path.join(name))
+        .filter(|config_path| config_path.is_file())
+        .collect();
+
+    match found.as_slice() {
+        [config_path] => Ok(config_path.clone()),
+        [] => Err(anyhow!(
+            "no config found in {}, expected one of {}",
+            path.display(),
+            CONFIG_FILES.join(", ")
+        )),
+        _ => Err(anyhow!(
+            "only one config is allowed, found {}",
+            found
+                .iter()
+                .map(|config_path| config_path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+fn parse_config(config_path: &Path) -> Result<Config> {
+    let content = read_to_string(config_path)
+        .context(format!("unable to read config {}", config_path.display()))?;
+
+    let config: Result<Config> = match config_path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&content).map_err(|e| anyhow!(e)),
+        Some("yaml") => serde_norway::from_str(&content).map_err(|e| anyhow!(e)),
+        _ => serde_json::from_str(&content).map_err(|e| anyhow!(e)),
+    };
+    config.context(format!("invalid config {}", config_path.display()))
+}
+
+/// Parses `config_path` followed by every include that applies to `host`, or
+/// every include at all without one, depth first. `stack` holds the files
+/// currently being included to catch cycles, files included twice are only
+/// read once.
+fn collect_configs(
+    repo: &Path,
+    host: Option<&Host>,
+    config_path: &Path,
+    stack: &mut Vec<PathBuf>,
+    configs: &mut Vec<(PathBuf, Config)>,
+) -> Result<()> {
+    let config_path = config_path.canonicalize().context(format!(
+        "unable to resolve include {}",
+        config_path.display()
+    ))?;
+    if stack.contains(&config_path) {
+        let cycle: Vec<String> = stack
+            .iter()
+            .chain([&config_path])
+            .map(|path| path.display().to_string())
+            .collect();
+        return Err(anyhow!("include cycle: {}", cycle.join(" -> ")));
+    }
+    if configs.iter().any(|(path, _)| *path == config_path) {
+        return Ok(());
+    }
+
+    let mut config = parse_config(&config_path)?;
+    let includes = std::mem::take(&mut config.include);
+    configs.push((config_path.clone(), config));
+
+    stack.push(config_path);
+    for include in includes
+        .iter()
+        .filter(|include| host.is_none_or(|host| include.matches(host)))
+    {
+        collect_configs(repo, host, &repo.join(include.path()), stack, configs)?;
+    }
+    stack.pop();
+    Ok(())
+}
+
+/// Loads the config of the repo at `path` with its includes layered on top
+/// in the order they are listed.
+pub fn load_config(path: &Path, host: &Host) -> Result<Config> {
+    let mut configs: Vec<(PathBuf, Config)> = vec![];
+    collect_configs(
+        path,
+        Some(host),
+        &config_path(path)?,
+        &mut vec![],
+        &mut configs,
+    )?;
+
+    let mut origins: BTreeMap<&Path, &Path> = BTreeMap::new();
+    for (config_path, config) in &configs {
+        for program in &config.config {
+            match origins.insert(&program.folder, config_path) {
+                Some(other) if other != config_path => {
+                    return Err(anyhow!(
+                        "folder {} is defined in both {} and {}",
+                        program.folder.display(),
+                        other.display(),
+                        config_path.display()
+                    ))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut configs = configs.into_iter().map(|(_, config)| config);
+    let mut merged = configs.next().context("no config found")?;
+    for config in configs {
+        merged.merge(config);
+    }
+    Ok(merged)
+}
+
+/// Every config file of the repo at `path` along with its contents, following
+/// includes regardless of the machines they apply to.
+pub fn all_configs(path: &Path) -> Result<Vec<(PathBuf, Config)>> {
+    let mut configs: Vec<(PathBuf, Config)> = vec![];
+    collect_configs(path, None, &config_path(path)?, &mut vec![], &mut configs)?;
+    Ok(configs)
+}
+
+/// What gets written to the target of links that are generated from their
+/// source rather than linked or copied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Content {
+    None,
+    Generated(Vec<u8>),
+    /// Decrypted the first time it is needed, so commands that never look at
+    /// the plaintext work without the keys.
+    Secret {
+        secrets: Secrets,
+        plaintext: OnceCell<Result<Vec<u8>, String>>,
+    },
+}
+
+/// A single file or directory from the repo and where it gets deployed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub mode: Mode,
+    content: Content,
+}
+
+impl Link {
+    pub fn new(source: PathBuf, target: PathBuf, mode: Mode) -> Self {
+        Self {
+            source,
+            target,
+            mode,
+            content: Content::None,
+        }
+    }
+
+    pub fn generated(source: PathBuf, target: PathBuf, mode: Mode, content: Vec<u8>) -> Self {
+        Self {
+            source,
+            target,
+            mode,
+            content: Content::Generated(content),
+        }
+    }
+
+    pub fn secret(source: PathBuf, target: PathBuf, secrets: Secrets) -> Self {
+        Self {
+            source,
+            target,
+            mode: Mode::Secret,
+            content: Content::Secret {
+                secrets,
+                plaintext: OnceCell::new(),
+            },
+        }
+    }
+
+    /// What gets written to `target`, decrypting secrets on first use.
+    pub fn content(&self) -> Result<&[u8]> {
+        match &self.content {
+            Content::None => Ok(&[]),
+            Content::Generated(content) => Ok(content),
+            Content::Secret { secrets, plaintext } => plaintext
+                .get_or_init(|| decrypt(&self.source, secrets).map_err(|e| format!("{e:#}")))
+                .as_deref()
+                .map_err(|e| anyhow!("{e}")),
+        }
+    }
+}
+
+type BaseDir = fn() -> Option<PathBuf>;
+
+/// Repo paths that deploy into an xdg base dir rather than straight into
+/// `$HOME`. `dirs` honours the matching `XDG_*` variables.
+const XDG_DIRS: &[(&str, BaseDir)] = &[
+    (".config", config_dir),
+    (".cache", cache_dir),
+    (".local/bin", executable_dir),
+    (".local/share", data_dir),
+    (".local/state", state_dir),
+];
+
+/// Where the xdg base dir for `relative` lives on this machine. Platforms
+/// without one fall back to the same path under `$HOME`.
+fn xdg_dir(relative: &Path, home_dir: &Path) -> Option<PathBuf> {
+    XDG_DIRS
+        .iter()
+        .find(|(dir, _)| relative == Path::new(dir))
+        .map(|(_, resolve)| resolve().unwrap_or_else(|| home_dir.join(relative)))
+}
+
+/// Every xdg base dir dotfox may deploy into.
+pub fn xdg_dirs() -> Vec<PathBuf> {
+    XDG_DIRS
+        .iter()
+        .filter_map(|(_, resolve)| resolve())
+        .collect()
+}
+
+const IGNORE_FILE: &str = ".dotfoxignore";
+
+fn is_template(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == TEMPLATE_EXTENSION)
+}
+
+/// One configured folder being walked for links.
+struct Folder<'a> {
+    program: &'a Programs,
+    home_dir: &'a Path,
+    variables: &'a BTreeMap<String, String>,
+    secrets: &'a Secrets,
+    ignore: Gitignore,
+}
+
+impl<'a> Folder<'a> {
+    /// Ignore rules come from the folder's `.dotfoxignore` followed by the
+    /// `ignore` patterns in the config, both relative to the folder.
+    fn new(
+        dir: &Path,
+        program: &'a Programs,
+        home_dir: &'a Path,
+        variables: &'a BTreeMap<String, String>,
+        secrets: &'a Secrets,
+        patterns: &[String],
+    ) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(dir);
+        builder.add_line(None, IGNORE_FILE)?;
+
+        let ignore_file = dir.join(IGNORE_FILE);
+        if ignore_file.is_file() {
+            if let Some(e) = builder.add(&ignore_file) {
+                return Err(anyhow!("invalid {}: {e}", ignore_file.display()));
+            }
+        }
+        for pattern in patterns {
+            builder.add_line(None, pattern)?;
+        }
+
+        Ok(Self {
+            program,
+            home_dir,
+            variables,
+            secrets,
+            ignore: builder.build()?,
+        })
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        self.ignore.matched(path, path.is_dir()).is_ignore()
+    }
+
+    /// Renders the template at `source`, dropping the `.tmpl` extension from
+    /// `target` if it has one.
+    fn render_entry(&self, source: PathBuf, target: PathBuf) -> Result<Vec<Link>> {
+        let template =
+            read_to_string(&source).context(format!("unable to read {}", source.display()))?;
+        let content = render(&template, self.variables)
+            .context(format!("unable to render {}", source.display()))?;
+        let target = if is_template(&source) {
+            target.with_extension("")
+        } else {
+            target
+        };
+        Ok(vec![Link::generated(
+            source,
+            target,
+            Mode::Template,
+            content.into_bytes(),
+        )])
+    }
+
+    /// Deploys the secret at `source` decrypted, dropping its `.gpg`, `.asc`
+    /// or `.age` extension from `target`.
+    fn secret_entry(&self, source: PathBuf, target: PathBuf) -> Vec<Link> {
+        let target = target.with_extension("");
+        vec![Link::secret(source, target, self.secrets.clone())]
+    }
+
+    /// Whether something inside `dir` can't be deployed by linking `dir`
+    /// whole, because it is ignored, a template that has to be rendered or a
+    /// secret that has to be decrypted.
+    fn must_unfold(&self, dir: &Path) -> Result<bool> {
+        for entry in read_dir(dir)? {
+            let path = entry?.path();
+            let is_dir = path.is_dir() && !path.is_symlink();
+            if self.is_ignored(&path)
+                || (!is_dir && (is_template(&path) || is_secret(&path)))
+                || (is_dir && self.must_unfold(&path)?)
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Links `source` to `target`, or with file granularity walks `source` and
+    /// links every file inside it so the directories themselves stay real.
+    /// Templates, and directories holding something that can't be linked
+    /// whole, are always walked file by file.
+    fn link_entry(&self, source: PathBuf, target: PathBuf) -> Result<Vec<Link>> {
+        let is_dir = source.is_dir() && !source.is_symlink();
+        let walk = self.program.granularity == Granularity::File
+            || self.program.template
+            || (is_dir && self.must_unfold(&source)?);
+
+        if !is_dir && is_secret(&source) {
+            Ok(self.secret_entry(source, target))
+        } else if !is_dir && (self.program.template || is_template(&source)) {
+            self.render_entry(source, target)
+        } else if walk && is_dir {
+            let mut links: Vec<Link> = vec![];
+            for file in read_dir(&source)? {
+                let file = file?.path();
+                if self.is_ignored(&file) {
+                    continue;
+                }
+                let target = target.join(file.file_name().unwrap_or_default());
+                links.append(&mut self.link_entry(file, target)?);
+            }
+            Ok(links)
+        } else {
+            Ok(vec![Link::new(source, target, self.program.mode)])
+        }
+    }
+
+    /// Resolves the entries of `dir`, which sits at `relative` inside the
+    /// folder and deploys to `target`. Unless the folder has its own target,
+    /// xdg base dirs and their parents (like `.local`) are merged into the
+    /// existing directories instead of being linked whole.
+    fn sync_dir(&self, dir: &Path, relative: &Path, target: &Path) -> Result<Vec<Link>> {
+        let mut sync_files: Vec<Link> = vec![];
+        let xdg = self.program.target.is_none();
+
+        for inner_file in read_dir(dir)? {
+            match inner_file {
+                Err(_e) =>
+                {
+                    #[cfg(debug_assertions)]
+                    print_error(_e.to_string())
+                }
+                Ok(file) => {
+                    let filename = file.file_name();
+                    let file = file.path();
+                    if self.is_ignored(&file) {
+                        continue;
+                    }
+                    let relative = relative.join(&filename);
+                    let is_parent = XDG_DIRS
+                        .iter()
+                        .any(|(dir, _)| Path::new(dir).starts_with(&relative));
+
+                    if !file.is_dir() || file.is_symlink() {
+                        sync_files.append(&mut self.link_entry(file, target.join(filename))?);
+                    } else if let Some(xdg_dir) = xdg_dir(&relative, self.home_dir).filter(|_| xdg)
+                    {
+                        sync_files.append(&mut self.sync_dir(&file, &relative, &xdg_dir)?);
+                    } else if is_parent && xdg {
+                        let target = target.join(filename);
+                        sync_files.append(&mut self.sync_dir(&file, &relative, &target)?);
+                    } else {
+                        sync_files.append(&mut self.link_entry(file, target.join(filename))?);
+                    }
+                }
+            }
+        }
+        Ok(sync_files)
+    }
+}
+
+/// The folders of `config` that apply to `host`, along with where they are
+/// inside the repo at `path`.
+pub fn matched_folders(
+    path: &Path,
+    config: Config,
+    host: &Host,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<Vec<(PathBuf, Programs)>> {
+    let mut folders = vec![];
+    for program in config.folders(host)? {
+        let relative = expand(&program.folder.to_string_lossy(), lookup)
+            .context(format!("invalid folder {}", program.folder.display()))?;
+        let dir = path.join(relative);
+        if !dir.is_dir() {
+            return Err(anyhow!("Path {} is not a direcotory", dir.display()));
+        }
+        folders.push((dir, program));
+    }
+    Ok(folders)
+}
+
+/// Resolves every link described by the config in `path` without touching
+/// the filesystem outside of the repo.
+pub fn resolve(path: &Path, host: &Host) -> Result<Vec<Link>> {
+    // links are created from these paths, so they must not be relative
+    let path = &path
+        .canonicalize()
+        .context(format!("unable to resolve {}", path.display()))?;
+    let home_dir = home_dir().context("unable to resolve home directory")?;
+    let config = load_config(path, host)?;
+    let ignore = config.ignore.clone();
+    let secrets = config.secrets.clone();
+    let variables = values(&resolve_variables(&config, host)?);
+    let lookup = lookup(&variables);
+
+    let mut sync_files: Vec<Link> = vec![];
+
+    for (dir, program) in matched_folders(path, config, host, &lookup)? {
+        let target = match &program.target {
+            Some(target) => expand_path(target, &lookup)
+                .context(format!("invalid target for {}", program.folder.display()))?,
+            None => home_dir.clone(),
+        };
+        let folder = Folder::new(&dir, &program, &home_dir, &variables, &secrets, &ignore)?;
+        let mut f = folder.sync_dir(&dir, Path::new(""), &target)?;
+        sync_files.append(&mut f);
+    }
+
+    if sync_files.is_empty() {
+        return Err(anyhow!("there are no files to sync"));
+    }
+
+    let mut targets: Vec<&PathBuf> = sync_files.iter().map(|file| &file.target).collect();
+    let pre_len = targets.len();
+    targets.sort();
+    targets.dedup();
+
+    if targets.len() != pre_len {
+        return Err(anyhow!(
+            "There is a conflict, resolution could not be complete"
+        ));
+    }
+
+    Ok(sync_files)
+}
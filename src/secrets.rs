@@ -0,0 +1,281 @@
+use super::{
+    config::{Backend, Secrets},
+    expand::{env_var, expand_path},
+};
+use age::{
+    armor::{ArmoredReader, ArmoredWriter, Format},
+    scrypt,
+    secrecy::SecretString,
+    ssh, x25519, Decryptor, Encryptor, Identity, IdentityFile, Recipient,
+};
+use anyhow::{anyhow, Context as anyhowContext, Result};
+use gpgme::{Context, Key, Protocol};
+use std::{
+    fs::{read, read_to_string, File},
+    io::{BufReader, Read, Write},
+    iter,
+    path::Path,
+};
+
+/// Extensions of files that get decrypted into place instead of linked.
+pub const SECRET_EXTENSIONS: &[&str] = &["gpg", "asc", "age"];
+
+/// Environment variable holding the passphrase for passphrase encrypted age
+/// files.
+pub const PASSPHRASE_VAR: &str = "DOTFOX_PASSPHRASE";
+
+/// Tag of the openpgp packet starting with `byte`, in either header format.
+fn packet_tag(byte: u8) -> Option<u8> {
+    match byte {
+        _ if byte & 0x80 == 0 => None,
+        _ if byte & 0x40 != 0 => Some(byte & 0x3f),
+        _ => Some((byte >> 2) & 0x0f),
+    }
+}
+
+/// Whether `path` has one of `SECRET_EXTENSIONS` and starts like an age file
+/// or an openpgp message. Keys and signatures sharing the extensions, like a
+/// public key in a `.asc` file, are linked as they are.
+pub fn is_secret(path: &Path) -> bool {
+    let Some(extension) = path.extension().and_then(|extension| extension.to_str()) else {
+        return false;
+    };
+    if !SECRET_EXTENSIONS.contains(&extension) {
+        return false;
+    }
+
+    let mut header = [0; 64];
+    let Ok(len) = File::open(path).and_then(|mut file| file.read(&mut header)) else {
+        return false;
+    };
+    let header = &header[..len];
+
+    if extension == "age" {
+        header.starts_with(b"age-encryption.org/")
+            || header
+                .trim_ascii_start()
+                .starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----")
+    } else {
+        // a public key or symmetric key encrypted session key opens a message
+        header
+            .trim_ascii_start()
+            .starts_with(b"-----BEGIN PGP MESSAGE-----")
+            || header
+                .first()
+                .is_some_and(|byte| matches!(packet_tag(*byte), Some(1 | 3)))
+    }
+}
+
+/// Extension `dotfox encrypt` gives files encrypted with `secrets`.
+pub fn extension(secrets: &Secrets, armor: bool) -> &'static str {
+    match secrets.backend {
+        Backend::Gpg if armor => "asc",
+        Backend::Gpg => "gpg",
+        Backend::Age => "age",
+    }
+}
+
+/// Decrypts the secret at `path`, `.age` files with age and everything else
+/// with gpg regardless of the configured backend.
+pub fn decrypt(path: &Path, secrets: &Secrets) -> Result<Vec<u8>> {
+    let ciphertext = read(path).context(format!("unable to read {}", path.display()))?;
+    let plaintext = if path.extension().is_some_and(|extension| extension == "age") {
+        age_decrypt(&ciphertext, secrets)
+    } else {
+        gpg_decrypt(&ciphertext)
+    };
+    plaintext.context(format!("unable to decrypt {}", path.display()))
+}
+
+/// Encrypts `plaintext` with the configured backend.
+pub fn encrypt(plaintext: &[u8], secrets: &Secrets, armor: bool) -> Result<Vec<u8>> {
+    match secrets.backend {
+        Backend::Gpg => gpg_encrypt(plaintext, &secrets.recipients, armor),
+        Backend::Age => age_encrypt(plaintext, secrets, armor),
+    }
+}
+
+fn gpg_decrypt(ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let mut ctx = Context::from_protocol(Protocol::OpenPgp)?;
+    let mut plaintext = Vec::new();
+
+    ctx.decrypt(ciphertext, &mut plaintext)
+        .map_err(|e| anyhow!("{e}"))?;
+    Ok(plaintext)
+}
+
+/// Encrypts `plaintext` to every key matching one of `recipients`.
+fn gpg_encrypt(plaintext: &[u8], recipients: &[String], armor: bool) -> Result<Vec<u8>> {
+    if recipients.is_empty() {
+        return Err(anyhow!("no recipients set in secrets.recipients"));
+    }
+
+    let mut ctx = Context::from_protocol(Protocol::OpenPgp)?;
+    ctx.set_armor(armor);
+
+    let mut keys: Vec<Key> = vec![];
+    for recipient in recipients {
+        let mut found: Vec<Key> = ctx
+            .find_keys([recipient.as_str()])?
+            .filter_map(|key| key.ok())
+            .filter(|key| key.can_encrypt())
+            .collect();
+        if found.is_empty() {
+            return Err(anyhow!("no usable key found for recipient {recipient}"));
+        }
+        keys.append(&mut found);
+    }
+
+    let mut ciphertext = Vec::new();
+    ctx.encrypt(&keys, plaintext, &mut ciphertext)
+        .map_err(|e| anyhow!("unable to encrypt: {e}"))?;
+    Ok(ciphertext)
+}
+
+fn passphrase() -> Result<SecretString> {
+    match env_var(PASSPHRASE_VAR) {
+        Some(passphrase) => Ok(SecretString::from(passphrase)),
+        None => Err(anyhow!("{PASSPHRASE_VAR} is not set")),
+    }
+}
+
+/// Parses an age X25519 recipient (`age1...`) or an ssh public key.
+fn age_recipient(recipient: &str) -> Result<Box<dyn Recipient + Send>> {
+    if let Ok(recipient) = recipient.parse::<x25519::Recipient>() {
+        return Ok(Box::new(recipient));
+    }
+    match recipient.parse::<ssh::Recipient>() {
+        Ok(recipient) => Ok(Box::new(recipient)),
+        Err(_) => Err(anyhow!("invalid age recipient {recipient}")),
+    }
+}
+
+/// Loads the identities in the file at `path`, either age identities as
+/// written by `age-keygen` or an unencrypted ssh private key.
+fn age_identities(path: &str) -> Result<Vec<Box<dyn Identity>>> {
+    let path = expand_path(path, &env_var)?;
+    let content = read_to_string(&path).context(format!("unable to read {}", path.display()))?;
+
+    if content.starts_with("-----BEGIN") {
+        let filename = Some(path.to_string_lossy().to_string());
+        let identity = ssh::Identity::from_buffer(content.as_bytes(), filename)?;
+        return Ok(vec![Box::new(identity)]);
+    }
+
+    let identities = IdentityFile::from_buffer(content.as_bytes())
+        .context(format!("invalid identity file {}", path.display()))?
+        .into_identities()?;
+    Ok(identities)
+}
+
+fn age_decrypt(ciphertext: &[u8], secrets: &Secrets) -> Result<Vec<u8>> {
+    let decryptor = Decryptor::new(ArmoredReader::new(BufReader::new(ciphertext)))?;
+    let mut reader = if decryptor.is_scrypt() {
+        let identity = scrypt::Identity::new(passphrase()?);
+        decryptor.decrypt(iter::once(&identity as &dyn Identity))?
+    } else {
+        let mut identities: Vec<Box<dyn Identity>> = vec![];
+        for path in &secrets.identities {
+            identities.append(&mut age_identities(path)?);
+        }
+        if identities.is_empty() {
+            return Err(anyhow!("no identities set in secrets.identities"));
+        }
+        decryptor.decrypt(identities.iter().map(|identity| identity.as_ref()))?
+    };
+
+    let mut plaintext = Vec::new();
+    reader.read_to_end(&mut plaintext)?;
+    Ok(plaintext)
+}
+
+/// Encrypts `plaintext` to the configured recipients, or with the passphrase
+/// from `DOTFOX_PASSPHRASE` when `secrets.passphrase` is set.
+fn age_encrypt(plaintext: &[u8], secrets: &Secrets, armor: bool) -> Result<Vec<u8>> {
+    let encryptor = if secrets.passphrase {
+        Encryptor::with_user_passphrase(passphrase()?)
+    } else {
+        if secrets.recipients.is_empty() {
+            return Err(anyhow!("no recipients set in secrets.recipients"));
+        }
+        let recipients = secrets
+            .recipients
+            .iter()
+            .map(|recipient| age_recipient(recipient))
+            .collect::<Result<Vec<_>>>()?;
+        Encryptor::with_recipients(recipients.iter().map(|recipient| recipient.as_ref() as _))?
+    };
+
+    let format = if armor {
+        Format::AsciiArmor
+    } else {
+        Format::Binary
+    };
+    let mut ciphertext = Vec::new();
+    let output = ArmoredWriter::wrap_output(&mut ciphertext, format)?;
+    let mut writer = encryptor.wrap_output(output)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?.finish()?;
+    Ok(ciphertext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+    use std::{env::set_var, fs::write};
+    use tempfile::tempdir;
+
+    const PLAINTEXT: &[u8] = b"machine example.com login fox password hunter2\n";
+
+    /// Encrypts to `secrets`, writes the ciphertext to `dir` and decrypts it
+    /// back the way sync does.
+    fn round_trip(dir: &Path, secrets: &Secrets, armor: bool) -> Vec<u8> {
+        let path = dir.join(format!(".netrc.{}", extension(secrets, armor)));
+        write(&path, encrypt(PLAINTEXT, secrets, armor).unwrap()).unwrap();
+        assert!(is_secret(&path));
+        decrypt(&path, secrets).unwrap()
+    }
+
+    #[test]
+    fn age_round_trips_with_a_generated_identity() {
+        let dir = tempdir().unwrap();
+        let identity = x25519::Identity::generate();
+        let identity_file = dir.path().join("key.txt");
+        write(&identity_file, identity.to_string().expose_secret()).unwrap();
+
+        let secrets = Secrets {
+            backend: Backend::Age,
+            recipients: vec![identity.to_public().to_string()],
+            identities: vec![identity_file.to_string_lossy().to_string()],
+            passphrase: false,
+        };
+        assert_eq!(round_trip(dir.path(), &secrets, false), PLAINTEXT);
+        assert_eq!(round_trip(dir.path(), &secrets, true), PLAINTEXT);
+
+        let stranger = dir.path().join("stranger.txt");
+        write(
+            &stranger,
+            x25519::Identity::generate().to_string().expose_secret(),
+        )
+        .unwrap();
+        let path = dir.path().join(".netrc.age");
+        let secrets = Secrets {
+            identities: vec![stranger.to_string_lossy().to_string()],
+            ..secrets
+        };
+        assert!(decrypt(&path, &secrets).is_err());
+    }
+
+    #[test]
+    fn age_round_trips_with_a_passphrase() {
+        set_var(PASSPHRASE_VAR, "correct horse battery staple");
+        let dir = tempdir().unwrap();
+        let secrets = Secrets {
+            backend: Backend::Age,
+            passphrase: true,
+            ..Default::default()
+        };
+        assert_eq!(round_trip(dir.path(), &secrets, false), PLAINTEXT);
+    }
+}
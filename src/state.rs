@@ -0,0 +1,47 @@
+use anyhow::{Context, Result};
+use dirs::home_dir;
+use std::{
+    fs::{create_dir_all, File},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// `$XDG_STATE_HOME/dotfox`, falling back to `~/.local/state/dotfox` on
+/// platforms without an xdg state dir.
+pub fn state_dir() -> Result<PathBuf> {
+    let base = match dirs::state_dir() {
+        Some(dir) => dir,
+        None => home_dir()
+            .context("unable to resolve home directory")?
+            .join(".local/state"),
+    };
+    Ok(base.join("dotfox"))
+}
+
+pub fn timestamp() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+const PROFILES: &str = "profiles.json";
+
+/// Profiles saved with `dotfox profile`, used when sync isn't given any.
+pub fn load_profiles() -> Result<Vec<String>> {
+    let path = state_dir()?.join(PROFILES);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let reader = File::open(&path).context(format!("unable to read {}", path.display()))?;
+    serde_json::from_reader(reader).context(format!("{} is corrupt", path.display()))
+}
+
+pub fn save_profiles(profiles: &[String]) -> Result<()> {
+    let dir = state_dir()?;
+    create_dir_all(&dir).context(format!("unable to create {}", dir.display()))?;
+    let path = dir.join(PROFILES);
+    let writer = File::create(&path).context(format!("unable to write {}", path.display()))?;
+    serde_json::to_writer_pretty(writer, profiles)?;
+    Ok(())
+}
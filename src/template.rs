@@ -0,0 +1,33 @@
+use super::expand::env_var;
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+
+pub const TEMPLATE_EXTENSION: &str = "tmpl";
+
+/// Replaces every `{{ name }}` in `input`. `{{ env.NAME }}` reads the
+/// environment, anything else has to be in `variables`.
+pub fn render(input: &str, variables: &BTreeMap<String, String>) -> Result<String> {
+    let mut output = String::new();
+    let mut rest = input;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+
+        let end = rest
+            .find("}}")
+            .ok_or_else(|| anyhow!("unterminated {{{{ in template"))?;
+        let name = rest[..end].trim();
+        let value = match name.strip_prefix("env.") {
+            Some(var) => env_var(var).ok_or_else(|| anyhow!("{var} is not set")),
+            None => variables
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow!("unknown template variable {name}")),
+        };
+        output.push_str(&value?);
+        rest = &rest[end + 2..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
@@ -1,24 +1,40 @@
-use crate::map::VerifyMap;
+use crate::map::{FixMap, PlanMap, ResultMap, StatusMap, VarsMap, VerifyMap};
 
 use super::{
-    config::Config,
+    backup::{self, Backup},
+    cli::{ConflictPolicy, Output, SyncArgs},
+    config::{Config, Event, Host, Mode},
+    deploy::{copy_recursive, hardlink_recursive, remove_deployed, write_generated, write_private},
+    expand::expand,
     git::{
-        add, commit,
+        add::{self, git_add_path},
+        commit,
         pull::{do_fetch, do_merge},
         push,
         shared::get_current_branch,
+        status::{ahead_behind, changes},
     },
+    hooks::run_hooks,
+    manifest::Manifest,
     map::Map,
+    output::Report,
+    plan::{self, link_state, points_into, Action, LinkState, Step},
+    prune::find_stale_links,
+    resolve::{all_configs, config_path, load_config, resolve, Link},
+    secrets,
+    state::{load_profiles, save_profiles},
+    variables::{lookup, resolve_variables, values},
 };
 use anyhow::{anyhow, Context, Result};
-use dirs::{config_dir, home_dir};
 use git2::{build::RepoBuilder, FetchOptions, RemoteCallbacks, Repository, StatusOptions};
 use git2_credentials::CredentialHandler;
-use owo_colors::{OwoColorize, Stream::Stdout, Style};
-use serde_json::from_reader;
+use owo_colors::{OwoColorize, Stream::Stderr, Style};
+use schemars::schema_for;
 use std::{
+    collections::BTreeMap,
     env::set_current_dir,
-    fs::{canonicalize, read_dir, OpenOptions},
+    fs::{create_dir_all, read, remove_dir_all, remove_file, write},
+    io::{stderr, stdin, Write},
     os::unix::fs::symlink,
     path::{Path, PathBuf},
     vec,
@@ -29,15 +45,16 @@ pub fn print_error(msg: String) {
     let style = Style::new().bold().red();
     eprintln!(
         "{}",
-        msg.if_supports_color(Stdout, |text| text.style(style))
+        msg.if_supports_color(Stderr, |text| text.style(style))
     );
 }
 
+/// Logs go to stderr so stdout only carries the report of a command.
 pub fn print_info(msg: String) {
     let style = Style::new().bold().green();
-    println!(
+    eprintln!(
         "{}",
-        msg.if_supports_color(Stdout, |text| text.style(style))
+        msg.if_supports_color(Stderr, |text| text.style(style))
     );
 }
 
@@ -111,137 +128,338 @@ pub fn clone(url: String, path: &Path) -> Result<()> {
     Ok(())
 }
 
-pub fn sync_config(path: PathBuf) -> Result<Vec<(PathBuf, PathBuf)>> {
-    let config_dir = match config_dir() {
-        Some(config) => config,
-        None => {
-            return Err(anyhow!("Unable to resolve xdg-config"));
+pub fn symlink_internal(file: &Path, target: &Path) {
+    match symlink(file, target) {
+        Ok(_) => {
+            print_info(format!("{} -> {}", target.display(), file.display()));
+        }
+        Err(e) => {
+            print_error(format!("unable to link {}: {e}", target.display()));
+        }
+    }
+}
+
+/// Puts `step.link.source` in place at `step.link.target` according to its mode.
+fn deploy(step: &Step) {
+    let result = match step.link.mode {
+        Mode::Symlink => {
+            symlink_internal(&step.link.source, &step.link.target);
+            return;
         }
+        Mode::Copy => copy_recursive(&step.link.source, &step.link.target),
+        Mode::Hardlink => hardlink_recursive(&step.link.source, &step.link.target),
+        Mode::Template => step
+            .link
+            .content()
+            .and_then(|content| write_generated(&step.link.source, &step.link.target, content)),
+        Mode::Secret => step
+            .link
+            .content()
+            .and_then(|content| write_private(&step.link.target, content)),
     };
-    let files = read_dir(path).expect("unable to read given path");
-    let mut sync_files: Vec<(PathBuf, PathBuf)> = vec![];
-    for file in files {
-        let file_actual = file?;
-        let file_path: PathBuf = file_actual.path();
-        let filename = file_actual.file_name();
-        let target = config_dir.join(&filename);
+    match result {
+        Ok(_) => print_info(format!(
+            "{} <- {} ({})",
+            step.link.target.display(),
+            step.link.source.display(),
+            step.link.mode
+        )),
+        Err(e) => print_error(format!("{e:#}")),
+    }
+}
 
-        sync_files.append(&mut vec![(file_path, target)]);
+/// Removes `target` whole, only for targets dotfox provably owns or when the
+/// conflict policy says to overwrite.
+fn remove_target(target: &Path) -> Result<()> {
+    if target.is_dir() && !target.is_symlink() {
+        remove_dir_all(target)
+    } else {
+        remove_file(target)
     }
-    Ok(sync_files)
+    .context(format!("unable to remove {}", target.display()))
 }
 
-pub fn symlink_internal(file: &Path, target: &Path) {
-    match symlink(file, target) {
-        Ok(_) => {
-            print_info(format!("{} -> {}", target.display(), file.display()));
+fn clear_conflict(step: &Step, policy: ConflictPolicy, backup: &mut Backup) -> Result<bool> {
+    match policy {
+        ConflictPolicy::Skip | ConflictPolicy::Abort => {
+            print_error(format!(
+                "skipping {}, a file is in the way",
+                step.link.target.display()
+            ));
+            Ok(false)
         }
-        Err(e) => {
-            if e.to_string() != *"File exists (os error 17)" {
-            } else if target.is_symlink() {
-                let target_canon = target.canonicalize().unwrap();
-                let source = file.canonicalize().unwrap();
-                if source != target_canon {
-                    print_info(format!(
-                        "{} is not symlinked to {}",
-                        target
-                            .display()
-                            .if_supports_color(Stdout, |text| text.cyan()),
-                        file.display()
-                            .if_supports_color(Stdout, |text| text.green())
-                    ))
-                }
+        ConflictPolicy::Backup => {
+            let moved = backup.store(&step.link.target)?;
+            print_info(format!(
+                "backed up {} to {}",
+                step.link.target.display(),
+                moved.display()
+            ));
+            Ok(true)
+        }
+        ConflictPolicy::Overwrite => {
+            remove_target(&step.link.target)?;
+            Ok(true)
+        }
+    }
+}
+
+fn apply_step(step: &Step, policy: ConflictPolicy, backup: &mut Backup) -> Result<()> {
+    match step.action {
+        Action::Create => deploy(step),
+        Action::CreateParent => {
+            if let Some(parent) = step.link.target.parent() {
+                create_dir_all(parent).context(format!("unable to create {}", parent.display()))?;
+            }
+            deploy(step);
+        }
+        Action::Update => {
+            if step.link.target.exists() || step.link.target.is_symlink() {
+                remove_target(&step.link.target)?;
+            }
+            deploy(step);
+        }
+        Action::WrongLink => print_info(format!(
+            "{} is not symlinked to {}",
+            step.link
+                .target
+                .display()
+                .if_supports_color(Stderr, |text| text.cyan()),
+            step.link
+                .source
+                .display()
+                .if_supports_color(Stderr, |text| text.green())
+        )),
+        Action::Blocked => {
+            if clear_conflict(step, policy, backup)? {
+                deploy(step);
+            }
+        }
+        Action::Remove => {
+            remove_file(&step.link.target)
+                .context(format!("unable to remove {}", step.link.target.display()))?;
+            print_info(format!("removed stale link {}", step.link.target.display()));
+        }
+        Action::Undecryptable => {
+            if let Err(e) = step.link.content() {
+                print_error(format!("skipping {}: {e:#}", step.link.target.display()));
             }
         }
+        Action::Ok => {}
     }
+    Ok(())
 }
 
-pub fn sync(path: &Path) -> Result<()> {
-    let home_dir = home_dir().context("unable to resolve home directory")?;
-    let config_path = path.join("dotfox.json");
+pub fn confirm(question: &str) -> Result<bool> {
+    eprint!("{question} [y/N] ");
+    stderr().flush()?;
 
-    if !config_path.exists() || config_path.is_dir() {
-        return Err(anyhow!(
-            "path for config {} does not exist",
-            config_path.display()
-        ));
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Removes `(link destination, link)` pairs found by prune, asking first
+/// unless `yes` is set.
+fn remove_links(links: &[(PathBuf, PathBuf)], yes: bool) -> Result<()> {
+    if links.is_empty() {
+        print_info("No stale links found".to_string());
+        return Ok(());
     }
 
-    let config_reader = OpenOptions::new()
-        .read(true)
-        .open(config_path)
-        .context("Failed to read config, does the path exist?")?;
+    let table: Vec<Map> = links
+        .iter()
+        .map(|link| Map::new(&link.0, &link.1))
+        .collect();
+    let table = Table::new(&table).to_string();
 
-    let config: Config = from_reader(config_reader)?;
+    eprintln!("{}", table.if_supports_color(Stderr, |text| text.bold()));
 
-    let mut files = config.folders()?;
-    let mut sync_files: Vec<(PathBuf, PathBuf)> = vec![];
-    let mut table: Vec<Map> = vec![];
+    if !yes && !confirm(&format!("Remove {} stale links?", links.len()))? {
+        print_info("Nothing removed".to_string());
+        return Ok(());
+    }
 
-    files.sort();
-    files.dedup();
+    for (_, link) in links {
+        remove_file(link).context(format!("unable to remove {}", link.display()))?;
+        print_info(format!("removed stale link {}", link.display()));
+    }
+    Ok(())
+}
 
+pub fn sync(path: &Path, args: &SyncArgs, output: Output) -> Result<()> {
+    let mut report = Report::new(output);
+    let host = Host::current()?.with_profiles(&args.profile);
+    if !host.profiles.is_empty() {
+        print_info(format!("Profiles: {}", host.profiles.join(", ")));
+    }
+    run_hooks(path, &host, Event::PreSync, args.dry_run)?;
     print_info("Resolving symlinks".to_string());
 
-    for dir in files {
-        let dir = path.join(dir);
-        if !dir.is_dir() {
-            return Err(anyhow!("Path {} is not a direcotory", dir.display()));
-        }
-        let in_files = read_dir(dir).unwrap();
-
-        for inner_file in in_files {
-            match inner_file {
-                Err(_e) =>
-                {
-                    #[cfg(debug_assertions)]
-                    print_error(_e.to_string())
-                }
-                Ok(file) => {
-                    let filename = file.file_name();
-                    let file = file.path();
-                    if filename == *".config" {
-                        let mut f = sync_config(file)?;
-                        sync_files.append(&mut f);
-                    } else {
-                        let target = home_dir.join(filename);
-                        sync_files.append(&mut vec![(file, target)])
-                    }
-                }
-            }
-        }
-    }
+    let sync_files = resolve(path, &host)?;
+    let table: Vec<Map> = sync_files
+        .iter()
+        .map(|file| Map::new(&file.source, &file.target))
+        .collect();
+    report.table("links", &table)?;
+    print_info("Symlinks resolved".to_string());
 
-    if !sync_files.is_empty() {
-        for file in &sync_files {
-            table.append(&mut vec![Map::new(&file.0, &file.1)])
-        }
+    let repo = path.canonicalize()?;
+    let mut manifest = Manifest::load()?;
+    let stale = manifest.stale(&repo, &sync_files);
+    let mut steps = plan::with_removals(plan::plan(&sync_files, &manifest), &stale);
+    let mut stray: Vec<(PathBuf, PathBuf)> = vec![];
+
+    if args.prune {
+        stray = find_stale_links(&repo, &sync_files)?;
+        stray.retain(|(_, link)| {
+            !steps
+                .iter()
+                .any(|step| step.action == Action::Remove && step.link.target == *link)
+        });
+    }
 
-        let pre_len = sync_files.len();
-        sync_files.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
-        sync_files.dedup_by(|a, b| a.1.eq(&b.1));
+    if args.dry_run {
+        steps.extend(stray.into_iter().map(|(source, target)| Step {
+            link: Link::new(source, target, Mode::Symlink),
+            action: Action::Remove,
+        }));
+        let table: Vec<PlanMap> = steps.iter().map(PlanMap::new).collect();
+        report.table("plan", &table)?;
+        run_hooks(path, &host, Event::PostSync, true)?;
+        return report.finish();
+    }
 
-        if sync_files.len() != pre_len {
+    if args.on_conflict == ConflictPolicy::Abort {
+        let blocked: Vec<String> = steps
+            .iter()
+            .filter(|step| step.action == Action::Blocked)
+            .map(|step| format!("{}", step.link.target.display()))
+            .collect();
+        if !blocked.is_empty() {
             return Err(anyhow!(
-                "There is a conflict, resolution could not be complete"
+                "files are in the way of links: {}",
+                blocked.join(", ")
             ));
         }
+    }
+
+    let mut backup = Backup::new(&repo);
+    for step in &steps {
+        apply_step(step, args.on_conflict, &mut backup)?;
+    }
 
-        let table = Table::new(&table).to_string();
+    if args.prune {
+        remove_links(&stray, args.yes)?;
+    }
 
-        println!("{}", table.if_supports_color(Stdout, |text| text.bold()));
-    } else {
-        return Err(anyhow!("there are no files to sync"));
+    manifest.record(&repo, &sync_files);
+    manifest.save()?;
+    run_hooks(path, &host, Event::PostSync, false)?;
+
+    let results: Vec<ResultMap> = steps
+        .iter()
+        .map(|step| {
+            ResultMap::new(
+                step,
+                link_state(&step.link, manifest.owns(&step.link.target)),
+            )
+        })
+        .collect();
+    report.value("results", &results)?;
+    report.finish()?;
+
+    let undecryptable = steps
+        .iter()
+        .filter(|step| step.action == Action::Undecryptable)
+        .count();
+    if undecryptable > 0 {
+        return Err(anyhow!("unable to decrypt {undecryptable} secrets"));
     }
+    Ok(())
+}
 
-    print_info("Symlinks resolved".to_string());
+pub fn unsync(path: &Path, restore: bool, profile: &[String]) -> Result<()> {
+    print_info("Resolving symlinks".to_string());
+
+    let sync_files = resolve(path, &Host::current()?.with_profiles(profile))?;
+    let repo = path.canonicalize()?;
+    let mut manifest = Manifest::load()?;
+    let owned: Vec<&PathBuf> = manifest
+        .entries
+        .iter()
+        .filter(|entry| entry.source.starts_with(&repo))
+        .map(|entry| &entry.target)
+        .collect();
+    let mut targets: Vec<&PathBuf> = sync_files.iter().map(|file| &file.target).collect();
+    targets.extend(owned);
+    targets.sort();
+    targets.dedup();
+
+    for target in targets {
+        if target.is_symlink() && points_into(target, &repo) {
+            remove_file(target).context(format!("unable to remove {}", target.display()))?;
+            print_info(format!("removed {}", target.display()));
+        }
+    }
+
+    // copies are removed file by file while they still match the repo,
+    // generated files while they are what dotfox put there or match the repo
+    for link in &sync_files {
+        if link.mode == Mode::Symlink || !link.target.exists() {
+            continue;
+        }
+        let kept = match link.mode {
+            Mode::Copy | Mode::Hardlink => {
+                remove_deployed(&link.source, &link.target, link.mode == Mode::Hardlink)?
+            }
+            _ if manifest.owns(&link.target) || plan::plan_link(link, false) == Action::Ok => {
+                remove_file(&link.target)
+                    .context(format!("unable to remove {}", link.target.display()))?;
+                vec![]
+            }
+            _ => vec![link.target.clone()],
+        };
+
+        if kept.is_empty() {
+            print_info(format!("removed {}", link.target.display()));
+        }
+        for path in kept {
+            print_error(format!(
+                "kept {}, it changed since dotfox put it there",
+                path.display()
+            ));
+        }
+    }
+
+    manifest.forget(&repo);
+    manifest.save()?;
 
-    for file in &sync_files {
-        symlink_internal(&file.0, &file.1);
+    if restore {
+        backup::restore_backups(path)?;
     }
     Ok(())
 }
 
+pub fn prune(path: &Path, yes: bool, profile: &[String]) -> Result<()> {
+    print_info("Resolving symlinks".to_string());
+
+    let sync_files = resolve(path, &Host::current()?.with_profiles(profile))?;
+    let repo = path.canonicalize()?;
+    let links = find_stale_links(&repo, &sync_files)?;
+
+    remove_links(&links, yes)
+}
+
+/// Restores the repo's backups and drops their manifest entries, so sync
+/// treats the restored files as the user's rather than replacing them.
+pub fn restore_backups(path: &Path) -> Result<()> {
+    let restored = backup::restore_backups(path)?;
+    let mut manifest = Manifest::load()?;
+    manifest.forget_targets(&restored);
+    manifest.save()
+}
+
 pub fn pull(path: &PathBuf) -> Result<()> {
     let repo = Repository::open(path).context(format!("Failed to open repo {}", path.display()))?;
 
@@ -252,79 +470,326 @@ pub fn pull(path: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-pub fn verify(path: &PathBuf) -> Result<()> {
-    let home_dir = home_dir().context("unable to resolve home directory")?;
-    let config_path = path.join("dotfox.json");
+pub fn vars(path: &Path, profile: &[String], output: Output) -> Result<()> {
+    let host = Host::current()?.with_profiles(profile);
+    let config = load_config(path, &host)?;
+    let variables = resolve_variables(&config, &host)?;
+
+    let table: Vec<VarsMap> = variables
+        .iter()
+        .map(|(name, variable)| VarsMap::new(name, variable))
+        .collect();
+    let mut report = Report::new(output);
+    report.table("variables", &table)?;
+    report.finish()
+}
 
-    if !config_path.exists() || config_path.is_dir() {
-        return Err(anyhow!(
-            "path for config {} does not exist",
-            config_path.display()
-        ));
+/// Repairs every target that isn't ok: wrong and dangling links and outdated
+/// copies are replaced, missing ones created and real files handled by
+/// `policy`. Prints each repaired target before and after, returning the
+/// exit code of what is left.
+fn fix_links(
+    repo: &Path,
+    files: &[Link],
+    states: &[LinkState],
+    policy: ConflictPolicy,
+    manifest: &mut Manifest,
+    report: &mut Report,
+) -> Result<i32> {
+    let broken: Vec<(&Link, LinkState)> = files
+        .iter()
+        .zip(states.iter().copied())
+        .filter(|(_, state)| *state != LinkState::Ok)
+        .collect();
+
+    if policy == ConflictPolicy::Abort {
+        let blocked: Vec<String> = broken
+            .iter()
+            .filter(|(_, state)| *state == LinkState::RealFile)
+            .map(|(link, _)| format!("{}", link.target.display()))
+            .collect();
+        if !blocked.is_empty() {
+            return Err(anyhow!(
+                "files are in the way of links: {}",
+                blocked.join(", ")
+            ));
+        }
     }
 
-    let config_reader = OpenOptions::new()
-        .read(true)
-        .open(config_path)
-        .context("Failed to read config, does the path exist?")?;
+    let mut backup = Backup::new(repo);
+    for (link, state) in &broken {
+        let action = match state {
+            LinkState::Missing => plan::plan_link(link, false),
+            LinkState::RealFile => Action::Blocked,
+            LinkState::Undecryptable => Action::Undecryptable,
+            _ => Action::Update,
+        };
+        let step = Step {
+            link: (*link).clone(),
+            action,
+        };
+        apply_step(&step, policy, &mut backup)?;
+    }
 
-    let config: Config = from_reader(config_reader)?;
+    manifest.record(repo, files);
+    manifest.save()?;
+
+    let mut code = 0;
+    let table: Vec<FixMap> = broken
+        .iter()
+        .map(|(link, before)| {
+            let after = link_state(link, manifest.owns(&link.target));
+            code |= after.exit_code();
+            FixMap::new(link, *before, after)
+        })
+        .collect();
+    report.table("fixed", &table)?;
+    Ok(code)
+}
 
-    let mut files = config.folders()?;
-    let mut sync_files: Vec<(PathBuf, PathBuf)> = vec![];
-    let mut table: Vec<VerifyMap> = vec![];
+/// Prints the state of every target, returning the exit code: 0 when all of
+/// them are ok, otherwise the bits of every state found or'd together. With
+/// `fix` broken targets are repaired first, see `fix_links`.
+pub fn verify(
+    path: &PathBuf,
+    fix: bool,
+    policy: ConflictPolicy,
+    profile: &[String],
+    output: Output,
+) -> Result<i32> {
+    let mut report = Report::new(output);
+    print_info("Resolving symlinks".to_string());
 
-    files.sort();
-    files.dedup();
+    let sync_files = resolve(path, &Host::current()?.with_profiles(profile))?;
+    let repo = path.canonicalize()?;
+    let mut manifest = Manifest::load()?;
+    let states: Vec<LinkState> = sync_files
+        .iter()
+        .map(|file| link_state(file, manifest.owns(&file.target)))
+        .collect();
+
+    if fix {
+        let code = fix_links(
+            &repo,
+            &sync_files,
+            &states,
+            policy,
+            &mut manifest,
+            &mut report,
+        )?;
+        report.finish()?;
+        return Ok(code);
+    }
 
-    print_info("Resolving symlinks".to_string());
+    let mut code = 0;
+    let table: Vec<VerifyMap> = sync_files
+        .iter()
+        .zip(&states)
+        .map(|(file, state)| {
+            code |= state.exit_code();
+            VerifyMap::new(&file.source, &file.target, *state)
+        })
+        .collect();
+    report.table("targets", &table)?;
+
+    for entry in manifest.stale(&repo, &sync_files) {
+        print_error(format!(
+            "{} is a stale link to {}, run sync to remove it",
+            entry.target.display(),
+            entry.source.display()
+        ));
+    }
 
-    for dir in files {
-        let dir = path.join(dir);
-        if !dir.is_dir() {
-            return Err(anyhow!("Path {} is not a direcotory", dir.display()));
+    report.finish()?;
+    Ok(code)
+}
+
+/// Encrypts `file` to the recipients in the config and stages the result if
+/// it lands inside the repo. The plaintext is left alone.
+pub fn encrypt(path: &Path, file: &Path, output: Option<PathBuf>, armor: bool) -> Result<()> {
+    let config = load_config(path, &Host::current()?)?;
+    let plaintext = read(file).context(format!("unable to read {}", file.display()))?;
+    let ciphertext = secrets::encrypt(&plaintext, &config.secrets, armor)?;
+
+    let output = output.unwrap_or_else(|| {
+        let extension = secrets::extension(&config.secrets, armor);
+        let mut name = file.as_os_str().to_owned();
+        name.push(format!(".{extension}"));
+        PathBuf::from(name)
+    });
+    write(&output, ciphertext).context(format!("unable to write {}", output.display()))?;
+    print_info(format!("{} -> {}", file.display(), output.display()));
+
+    let repo = Repository::open(path).context(format!("Failed to open repo {}", path.display()))?;
+    let workdir = repo
+        .workdir()
+        .context("repo has no working directory")?
+        .canonicalize()?;
+    let output = output.canonicalize()?;
+    if let Ok(relative) = output.strip_prefix(&workdir) {
+        git_add_path(&repo, relative)?;
+        print_info(format!("staged {}", relative.display()));
+    }
+    if file.canonicalize()?.starts_with(&workdir) {
+        print_error(format!(
+            "{} is inside the repo, remove it or add it to .gitignore before committing",
+            file.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Saves `profiles` as the ones used on this machine, or prints the saved
+/// ones when there are none to set.
+pub fn profile(set: &[String], clear: bool) -> Result<()> {
+    if clear {
+        save_profiles(&[])?;
+        print_info("Cleared saved profiles".to_string());
+    } else if !set.is_empty() {
+        save_profiles(set)?;
+        print_info(format!("Saved profiles: {}", set.join(", ")));
+    } else {
+        let profiles = load_profiles()?;
+        if profiles.is_empty() {
+            print_info("No profiles saved".to_string());
+        } else {
+            print_info(format!("Saved profiles: {}", profiles.join(", ")));
         }
-        let in_files = read_dir(dir).unwrap();
-
-        for inner_file in in_files {
-            match inner_file {
-                Err(_e) =>
-                {
-                    #[cfg(debug_assertions)]
-                    print_error(_e.to_string())
-                }
-                Ok(file) => {
-                    let filename = file.file_name();
-                    let file = file.path();
-                    if filename == *".config" {
-                        let mut f = sync_config(file)?;
-                        sync_files.append(&mut f);
-                    } else {
-                        let target = home_dir.join(filename);
-                        sync_files.append(&mut vec![(file, target)])
-                    }
+    }
+    Ok(())
+}
+
+/// Loads the config strictly along with every include, whichever machines
+/// they apply to, and checks that every folder in them is a directory in the
+/// repo.
+pub fn validate(path: &Path) -> Result<()> {
+    let host = Host::current()?;
+    let config = load_config(path, &host)?;
+    let variables = values(&resolve_variables(&config, &host)?);
+    let lookup = lookup(&variables);
+    let mut problems = 0;
+
+    for (config_file, config) in all_configs(path)? {
+        for program in &config.config {
+            let folder = match expand(&program.folder.to_string_lossy(), &lookup) {
+                Ok(folder) => path.join(folder),
+                Err(e) => {
+                    print_error(format!(
+                        "invalid folder {} in {}: {e}",
+                        program.folder.display(),
+                        config_file.display()
+                    ));
+                    problems += 1;
+                    continue;
                 }
+            };
+            if !folder.exists() {
+                print_error(format!(
+                    "folder {} in {} does not exist",
+                    folder.display(),
+                    config_file.display()
+                ));
+                problems += 1;
+            } else if !folder.is_dir() {
+                print_error(format!(
+                    "folder {} in {} is not a directory",
+                    folder.display(),
+                    config_file.display()
+                ));
+                problems += 1;
             }
         }
     }
 
-    if !sync_files.is_empty() {
-        for file in &sync_files {
-            let mut map = VerifyMap::new(&file.0, &file.1);
-            let resolved_target = canonicalize(&file.1)?;
+    if problems > 0 {
+        return Err(anyhow!("found {problems} problems in the config"));
+    }
+    print_info(format!("{} is valid", config_path(path)?.display()));
+    Ok(())
+}
 
-            if &file.0 != &resolved_target {
-                map.taint();
-            }
-            table.append(&mut vec![map])
+pub fn schema(output: Option<PathBuf>) -> Result<()> {
+    let schema = serde_json::to_string_pretty(&schema_for!(Config))?;
+    match output {
+        Some(output) => {
+            write(&output, schema + "\n")
+                .context(format!("unable to write {}", output.display()))?;
+            print_info(format!("wrote schema to {}", output.display()));
         }
+        None => println!("{schema}"),
+    }
+    Ok(())
+}
 
-        let table = Table::new(&table).to_string();
+/// Prints uncommitted changes, how far the branch is from its upstream and
+/// every link that isn't in place. Fails when any of them is out of order.
+pub fn status(path: &Path, profile: &[String], output: Output) -> Result<()> {
+    let mut report = Report::new(output);
+    let repo = Repository::open(path).context(format!("Failed to open repo {}", path.display()))?;
+    let mut out_of_order = false;
+
+    let changes = changes(&repo)?;
+    let table: Vec<StatusMap> = changes
+        .iter()
+        .map(|(path, status)| StatusMap::new(path, status))
+        .collect();
+    if table.is_empty() {
+        print_info("No uncommitted changes".to_string());
+        report.value("changes", &table)?;
+    } else {
+        out_of_order = true;
+        report.table("changes", &table)?;
+    }
+
+    let upstream = ahead_behind(&repo)?;
+    report.value(
+        "upstream",
+        &upstream.as_ref().map(|(name, ahead, behind)| {
+            serde_json::json!({ "name": name, "ahead": ahead, "behind": behind })
+        }),
+    )?;
+    match upstream {
+        Some((upstream, 0, 0)) => print_info(format!("Up to date with {upstream}")),
+        Some((upstream, ahead, behind)) => {
+            out_of_order = true;
+            print_error(format!("{ahead} ahead and {behind} behind {upstream}"));
+        }
+        None => print_info("No upstream branch".to_string()),
+    }
 
-        println!("{}", table.if_supports_color(Stdout, |text| text.bold()));
+    let sync_files = resolve(path, &Host::current()?.with_profiles(profile))?;
+    let manifest = Manifest::load()?;
+    let states: Vec<(&Link, LinkState)> = sync_files
+        .iter()
+        .map(|link| (link, link_state(link, manifest.owns(&link.target))))
+        .collect();
+
+    let table: Vec<VerifyMap> = states
+        .iter()
+        .filter(|(_, state)| *state != LinkState::Ok)
+        .map(|(link, state)| VerifyMap::new(&link.source, &link.target, *state))
+        .collect();
+    if table.is_empty() {
+        report.value("links", &table)?;
     } else {
-        return Err(anyhow!("there are no files to sync"));
+        out_of_order = true;
+        report.table("links", &table)?;
     }
 
+    let mut counts: BTreeMap<LinkState, usize> = BTreeMap::new();
+    for (_, state) in &states {
+        *counts.entry(*state).or_default() += 1;
+    }
+    let summary: Vec<String> = counts
+        .iter()
+        .map(|(state, count)| format!("{count} {state}"))
+        .collect();
+    print_info(format!("Links: {}", summary.join(", ")));
+    report.value("summary", &counts)?;
+    report.finish()?;
+
+    if out_of_order {
+        return Err(anyhow!("{} is out of order", path.display()));
+    }
     Ok(())
 }
@@ -0,0 +1,62 @@
+use super::{
+    config::{Config, Host},
+    expand::env_var,
+};
+use anyhow::{Context, Result};
+use dirs::home_dir;
+use std::collections::BTreeMap;
+
+/// A resolved variable and where its value came from.
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub value: String,
+    pub source: String,
+}
+
+/// Built-in variables, overlaid with `variables` from the config and then
+/// every matching override in the order they are listed, so the last match
+/// wins.
+pub fn resolve_variables(config: &Config, host: &Host) -> Result<BTreeMap<String, Variable>> {
+    let home = home_dir().context("unable to resolve home directory")?;
+    let mut variables: BTreeMap<String, Variable> = BTreeMap::new();
+    let mut set = |name: &str, value: &str, source: &str| {
+        variables.insert(
+            name.to_string(),
+            Variable {
+                value: value.to_string(),
+                source: source.to_string(),
+            },
+        );
+    };
+
+    set("hostname", &host.hostname, "builtin");
+    set("os", &host.os, "builtin");
+    set("username", &host.username, "builtin");
+    set("home", &home.to_string_lossy(), "builtin");
+
+    for (name, value) in &config.variables {
+        set(name, value, "variables");
+    }
+    for (index, over) in config.overrides.iter().enumerate() {
+        if !over.matches(host) {
+            continue;
+        }
+        let source = format!("overrides[{index}] {}", over.describe());
+        for (name, value) in &over.variables {
+            set(name, value, &source);
+        }
+    }
+    Ok(variables)
+}
+
+pub fn values(variables: &BTreeMap<String, Variable>) -> BTreeMap<String, String> {
+    variables
+        .iter()
+        .map(|(name, variable)| (name.clone(), variable.value.clone()))
+        .collect()
+}
+
+/// Lookup for `expand`, user variables shadow the environment.
+pub fn lookup<'a>(variables: &'a BTreeMap<String, String>) -> impl Fn(&str) -> Option<String> + 'a {
+    move |name| variables.get(name).cloned().or_else(|| env_var(name))
+}